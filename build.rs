@@ -1,208 +1,221 @@
-use std::io;
 use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::{env, fs};
 
-fn main() {
-    let c_path = PathBuf::from("c/lwext4")
-        .canonicalize()
-        .expect("cannot canonicalize path");
-
-    let lwext4_make = Path::new("c/lwext4/toolchain/musl-generic.cmake");
-    let lwext4_patch = Path::new("c/lwext4-make.patch").canonicalize().unwrap();
+/// lwext4 sources, relative to `c/lwext4/src`.
+const LWEXT4_SRC: &[&str] = &[
+    "ext4.c",
+    "ext4_balloc.c",
+    "ext4_bcache.c",
+    "ext4_bitmap.c",
+    "ext4_block_group.c",
+    "ext4_blockdev.c",
+    "ext4_crc32.c",
+    "ext4_debug.c",
+    "ext4_dir.c",
+    "ext4_dir_idx.c",
+    "ext4_extent.c",
+    "ext4_fs.c",
+    "ext4_hash.c",
+    "ext4_ialloc.c",
+    "ext4_inode.c",
+    "ext4_journal.c",
+    "ext4_mbr.c",
+    "ext4_mkfs.c",
+    "ext4_super.c",
+    "ext4_trans.c",
+    "ext4_xattr.c",
+];
+
+/// Configuration of lwext4, see `c/lwext4/include/ext4_config.h`.
+///
+/// Cargo features switch the optional parts, and the table sizes can be set
+/// with the `LWEXT4_MOUNTPOINTS_COUNT`, `LWEXT4_BLOCKDEVS_COUNT` and
+/// `LWEXT4_BLOCK_DEV_CACHE_SIZE` environment variables.
+fn lwext4_config() -> Vec<(&'static str, String)> {
+    let feature = |name: &str| env::var_os(format!("CARGO_FEATURE_{}", name)).is_some();
+    let flag = |on: bool| String::from(if on { "1" } else { "0" });
+    let count = |var: &str, default: u32| {
+        println!("cargo:rerun-if-env-changed={}", var);
+        match env::var(var) {
+            Ok(v) => match v.trim().parse::<u32>() {
+                Ok(n) if n > 0 => n.to_string(),
+                _ => panic!("{} must be a positive number, got {:?}", var, v),
+            },
+            Err(_) => default.to_string(),
+        }
+    };
+
+    let journal = feature("JOURNAL");
+    let xattr = feature("XATTR");
+    let extents = feature("EXTENTS");
+    // The feature set decides which on-disk features a volume may use to be
+    // mounted; pick the smallest one covering what is enabled.
+    let feature_set = if extents {
+        "F_SET_EXT4"
+    } else if journal || xattr {
+        "F_SET_EXT3"
+    } else {
+        "F_SET_EXT2"
+    };
+
+    vec![
+        ("CONFIG_USE_DEFAULT_CONFIG", flag(false)),
+        ("CONFIG_DEBUG_PRINTF", flag(feature("DEBUG_PRINTF"))),
+        ("CONFIG_DEBUG_ASSERT", flag(feature("DEBUG_ASSERT"))),
+        ("CONFIG_HAVE_OWN_OFLAGS", flag(true)),
+        ("CONFIG_HAVE_OWN_ERRNO", flag(true)),
+        ("CONFIG_HAVE_OWN_ASSERT", flag(true)),
+        ("CONFIG_USE_USER_MALLOC", flag(false)),
+        ("CONFIG_EXT_FEATURE_SET_LVL", String::from(feature_set)),
+        ("CONFIG_JOURNALING_ENABLE", flag(journal)),
+        ("CONFIG_XATTR_ENABLE", flag(xattr)),
+        ("CONFIG_EXTENTS_ENABLE", flag(extents)),
+        (
+            "CONFIG_BLOCK_DEV_CACHE_SIZE",
+            count("LWEXT4_BLOCK_DEV_CACHE_SIZE", 16),
+        ),
+        (
+            "CONFIG_EXT4_BLOCKDEVS_COUNT",
+            count("LWEXT4_BLOCKDEVS_COUNT", 8),
+        ),
+        (
+            "CONFIG_EXT4_MOUNTPOINTS_COUNT",
+            count("LWEXT4_MOUNTPOINTS_COUNT", 8),
+        ),
+    ]
+}
 
-    if !Path::new(lwext4_make).exists() {
+fn main() {
+    let lwext4 = Path::new("c/lwext4");
+    if !lwext4.join("src/ext4.c").exists() {
         println!("Retrieve lwext4 source code");
         let git_status = Command::new("git")
             .args(&["submodule", "update", "--init", "--recursive"])
             .status()
             .expect("failed to execute process: git submodule");
         assert!(git_status.success());
-
-        println!("Applying patch to lwext4 src");
-        // Try using the patch command directly first
-        let patch_result = Command::new("patch")
-            .args(&[
-                "-p1",
-                "-d",
-                c_path.to_str().unwrap(),
-                "-i",
-                lwext4_patch.to_str().unwrap(),
-            ])
-            .status();
-
-        match patch_result {
-            Ok(status) if status.success() => {
-                println!("Patch applied successfully using patch command")
-            }
-            _ => {
-                // Fallback to manual patching if patch command fails
-                println!("Patch command failed, falling back to manual patching");
-                apply_patch_manually(&lwext4_patch, &c_path)
-                    .expect("Failed to apply patch manually");
-            }
-        }
-
-        fs::copy(
-            "c/musl-generic.cmake",
-            "c/lwext4/toolchain/musl-generic.cmake",
-        )
-        .unwrap();
-
-        if Path::new("c/lwext4/toolchain/musl-generic.cmake").exists() {
-            println!("Successfully created musl-generic.cmake file");
-        } else {
-            println!("Failed to create musl-generic.cmake file");
-        }
     }
 
-    let arch = env::var("CARGO_CFG_TARGET_ARCH").unwrap();
-    let lwext4_lib = &format!("lwext4-{}", arch);
-    let lwext4_lib_path = &format!("c/lwext4/lib{}.a", lwext4_lib);
-    if !Path::new(lwext4_lib_path).exists() {
-        let status = Command::new("make")
-            .args(&[
-                "musl-generic",
-                "-C",
-                c_path.to_str().expect("invalid path of lwext4"),
-            ])
-            .arg(&format!("ARCH={}", arch))
-            .status()
-            .expect("failed to execute process: make lwext4");
-        assert!(status.success());
-
-        let cc = &format!("{}-linux-musl-gcc", arch);
-        let output = Command::new(cc)
-            .args(["-print-sysroot"])
-            .output()
-            .expect("failed to execute process: gcc -print-sysroot");
-
-        let sysroot = core::str::from_utf8(&output.stdout).unwrap();
-        let sysroot = sysroot.trim_end();
-        let sysroot_inc = &format!("-I{}/include/", sysroot);
-        generates_bindings_to_rust(sysroot_inc);
+    let target = env::var("TARGET").unwrap();
+    let target_os = env::var("CARGO_CFG_TARGET_OS").unwrap();
+    let bare_metal = target_os == "none";
+    let std = env::var_os("CARGO_FEATURE_STD").is_some();
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    let config = lwext4_config();
+
+    // ext4_config.h includes this header unless told to use its defaults;
+    // the configuration itself is passed on the command line.
+    let generated = out_dir.join("include/generated");
+    fs::create_dir_all(&generated).unwrap();
+    fs::write(
+        generated.join("ext4_config.h"),
+        "/* Configured by build.rs with -D flags. */\n",
+    )
+    .unwrap();
+
+    let mut build = cc::Build::new();
+    build
+        .include(lwext4.join("include"))
+        .include(out_dir.join("include"))
+        .flag_if_supported("-std=gnu99")
+        .flag("-fno-builtin")
+        .warnings(false);
+    for (name, value) in &config {
+        build.define(name, value.as_str());
     }
-
-    println!("cargo:rustc-link-lib=static={lwext4_lib}");
-    println!(
-        "cargo:rustc-link-search=native={}",
-        c_path.to_str().unwrap()
-    );
-    println!("cargo:rerun-if-changed=c/wrapper.h");
-    println!("cargo:rerun-if-changed={}", c_path.to_str().unwrap());
-}
-
-/// Manually applies the specific lwext4 patch
-fn apply_patch_manually(_patch_path: &Path, target_dir: &Path) -> io::Result<()> {
-    println!("Applying specific lwext4 patches directly");
-
-    // Patch 1: CMakeLists.txt
-    let cmake_path = target_dir.join("CMakeLists.txt");
-    if cmake_path.exists() {
-        let mut content = fs::read_to_string(&cmake_path)?;
-
-        // Replace the config flags
-        content = content.replace(
-            "    add_definitions(-DCONFIG_HAVE_OWN_OFLAGS=0)
-    add_definitions(-DCONFIG_HAVE_OWN_ERRNO=0)
-    add_definitions(-DCONFIG_HAVE_OWN_ASSERT=0)",
-            "
-    add_definitions(-DCONFIG_DEBUG_PRINTF=1)
-    add_definitions(-DCONFIG_DEBUG_ASSERT=1)
-
-    add_definitions(-DCONFIG_HAVE_OWN_OFLAGS=1)
-    add_definitions(-DCONFIG_HAVE_OWN_ERRNO=1)
-    add_definitions(-DCONFIG_HAVE_OWN_ASSERT=1)
-    add_definitions(-DCONFIG_USE_USER_MALLOC=0)",
-        );
-
-        println!("Patching: {}", cmake_path.display());
-        fs::write(&cmake_path, content)?;
-    } else {
-        println!(
-            "Warning: CMakeLists.txt not found at {}",
-            cmake_path.display()
-        );
+    for src in LWEXT4_SRC {
+        build.file(lwext4.join("src").join(src));
     }
-
-    // Patch 2: Makefile
-    let makefile_path = target_dir.join("Makefile");
-    if makefile_path.exists() {
-        let mut content = fs::read_to_string(&makefile_path)?;
-
-        // Add new flags
-        content = content.replace(
-            "-DVERSION=$(VERSION)                                  \\",
-            "-DVERSION=$(VERSION)                                  \\
-\t-DLWEXT4_BUILD_SHARED_LIB=OFF \\
-\t-DCMAKE_INSTALL_PREFIX=./install \\",
-        );
-
-        // Add musl-generic target
-        content = content.replace(
-            "endef\n\ngeneric:",
-            "endef\n\nARCH ?= x86_64\n#Output: src/liblwext4.a\nmusl-generic:\n\t$(call generate_common,$@)\n\tcd build_$@ && make lwext4\n\tcp build_$@/src/liblwext4.a ./liblwext4-$(ARCH).a\n\ngeneric:"
-        );
-
-        println!("Patching: {}", makefile_path.display());
-        fs::write(&makefile_path, content)?;
-    } else {
-        println!("Warning: Makefile not found at {}", makefile_path.display());
+    // Journal transactions for the inode updates done from Rust.
+    build.file("c/trans.c");
+
+    if bare_metal {
+        // No libc headers on bare metal: use the declarations in c/include,
+        // and clang unless a C compiler is set for the target.
+        build.include("c/include").flag("-ffreestanding");
+        let cc_vars = [
+            format!("CC_{}", target),
+            format!("CC_{}", target.replace('-', "_")),
+            String::from("TARGET_CC"),
+            String::from("CC"),
+        ];
+        if cc_vars.iter().all(|v| env::var_os(v).is_none()) {
+            build.compiler("clang");
+        }
     }
-
-    // Patch 3: src/CMakeLists.txt
-    let src_cmake_path = target_dir.join("src/CMakeLists.txt");
-    if src_cmake_path.exists() {
-        let mut content = fs::read_to_string(&src_cmake_path)?;
-
-        // Add new source file and modify build
-        content = content.replace(
-            "aux_source_directory(. LWEXT4_SRC)",
-            "aux_source_directory(. LWEXT4_SRC)\nset(M_SRC \"../../ulibc.c\")",
-        );
-
-        content = content.replace(
-            "add_library(lwext4 STATIC ${LWEXT4_SRC})",
-            "add_library(lwext4 STATIC ${LWEXT4_SRC} ${M_SRC})",
-        );
-
-        println!("Patching: {}", src_cmake_path.display());
-        fs::write(&src_cmake_path, content)?;
-    } else {
-        println!(
-            "Warning: src/CMakeLists.txt not found at {}",
-            src_cmake_path.display()
-        );
+    if bare_metal && !std {
+        // libc routines lwext4 needs and the OS does not provide.
+        build.file("c/ulibc.c");
     }
+    build.compile("lwext4");
+
+    generates_bindings_to_rust(&out_dir, &target, bare_metal, &config);
 
-    println!("Manual patching completed");
-    Ok(())
+    println!("cargo:rerun-if-changed=c/wrapper.h");
+    println!("cargo:rerun-if-changed=c/ulibc.c");
+    println!("cargo:rerun-if-changed=c/trans.c");
+    println!("cargo:rerun-if-changed=c/include");
+    println!("cargo:rerun-if-changed={}", lwext4.display());
 }
 
-#[cfg(target_arch = "x86_64")]
-fn generates_bindings_to_rust(_mpath: &str) {}
+/// Uses the checked-in `src/bindings.rs`, generated for x86_64. Its lwext4
+/// structures only have fixed-width integer and pointer fields, and
+/// `src/layout.rs` checks at compile time that they match the C layout of
+/// the target. The libc items in it are x86_64 specific and unused.
+#[cfg(not(feature = "bindgen"))]
+fn generates_bindings_to_rust(
+    _out_dir: &Path,
+    _target: &str,
+    _bare_metal: bool,
+    _config: &[(&str, String)],
+) {
+}
 
-#[cfg(not(target_arch = "x86_64"))]
-fn generates_bindings_to_rust(mpath: &str) {
-    let bindings = bindgen::Builder::default()
+/// Generates the bindings for the build target into `$OUT_DIR/bindings.rs`.
+#[cfg(feature = "bindgen")]
+fn generates_bindings_to_rust(
+    out_dir: &Path,
+    target: &str,
+    bare_metal: bool,
+    config: &[(&str, String)],
+) {
+    let mut builder = bindgen::Builder::default()
         .use_core()
         // The input header we would like to generate bindings for.
         .header("c/wrapper.h")
-        //.clang_arg("--sysroot=/path/to/sysroot")
-        .clang_arg(mpath)
-        //.clang_arg("-I../../ulib/axlibc/include")
+        .clang_arg(format!("--target={}", clang_target(target)))
         .clang_arg("-I./c/lwext4/include")
-        .clang_arg("-I./c/lwext4/build_musl-generic/include/")
+        .clang_arg(format!("-I{}", out_dir.join("include").display()))
         .layout_tests(false)
         // Tell cargo to invalidate the built crate whenever any of the included header files changed.
-        .parse_callbacks(Box::new(bindgen::CargoCallbacks::new()))
-        // Finish the builder and generate the bindings.
-        .generate()
-        .expect("Unable to generate bindings");
+        .parse_callbacks(Box::new(bindgen::CargoCallbacks::new()));
+    if bare_metal {
+        builder = builder
+            .clang_arg("-I./c/include")
+            .clang_arg("-ffreestanding");
+    }
+    for (name, value) in config {
+        builder = builder.clang_arg(format!("-D{}={}", name, value));
+    }
+    let bindings = builder.generate().expect("Unable to generate bindings");
 
-    // Write the bindings to the $OUT_DIR/bindings.rs file.
-    let out_path = PathBuf::from("src");
     bindings
-        .write_to_file(out_path.join("bindings.rs"))
+        .write_to_file(out_dir.join("bindings.rs"))
         .expect("Couldn't write bindings!");
 }
+
+/// Turns a Rust target triple into one clang accepts, e.g.
+/// `riscv64gc-unknown-none-elf` into `riscv64-unknown-none-elf`.
+#[cfg(feature = "bindgen")]
+fn clang_target(target: &str) -> String {
+    let (arch, rest) = target.split_once('-').unwrap_or((target, ""));
+    let arch = if arch.starts_with("riscv64") {
+        "riscv64"
+    } else if arch.starts_with("riscv32") {
+        "riscv32"
+    } else {
+        arch
+    };
+    format!("{}-{}", arch, rest.trim_end_matches("-softfloat"))
+}
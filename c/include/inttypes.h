@@ -0,0 +1,30 @@
+/* Minimal libc declarations for bare-metal builds of lwext4. */
+#ifndef LWEXT4_SHIM_INTTYPES_H
+#define LWEXT4_SHIM_INTTYPES_H
+
+#include <stdint.h>
+
+#ifdef __LP64__
+#define __PRI64 "l"
+#else
+#define __PRI64 "ll"
+#endif
+
+#define PRId8 "d"
+#define PRId16 "d"
+#define PRId32 "d"
+#define PRId64 __PRI64 "d"
+#define PRIi32 "i"
+#define PRIi64 __PRI64 "i"
+#define PRIu8 "u"
+#define PRIu16 "u"
+#define PRIu32 "u"
+#define PRIu64 __PRI64 "u"
+#define PRIx8 "x"
+#define PRIx16 "x"
+#define PRIx32 "x"
+#define PRIx64 __PRI64 "x"
+#define PRIX32 "X"
+#define PRIX64 __PRI64 "X"
+
+#endif
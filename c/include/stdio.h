@@ -0,0 +1,14 @@
+/* Minimal libc declarations for bare-metal builds of lwext4. */
+#ifndef LWEXT4_SHIM_STDIO_H
+#define LWEXT4_SHIM_STDIO_H
+
+#include <stddef.h>
+
+typedef struct _FILE FILE;
+
+extern FILE *const stdout;
+
+int printf(const char *restrict fmt, ...);
+int fflush(FILE *f);
+
+#endif
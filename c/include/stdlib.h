@@ -0,0 +1,14 @@
+/* Minimal libc declarations for bare-metal builds of lwext4. */
+#ifndef LWEXT4_SHIM_STDLIB_H
+#define LWEXT4_SHIM_STDLIB_H
+
+#include <stddef.h>
+
+void *malloc(size_t size);
+void *calloc(size_t m, size_t n);
+void *realloc(void *p, size_t size);
+void free(void *p);
+void qsort(void *base, size_t nel, size_t width,
+	   int (*comp)(const void *, const void *));
+
+#endif
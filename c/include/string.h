@@ -0,0 +1,18 @@
+/* Minimal libc declarations for bare-metal builds of lwext4. The functions
+ * come from compiler_builtins, ulibc.c and src/ulibc.rs. */
+#ifndef LWEXT4_SHIM_STRING_H
+#define LWEXT4_SHIM_STRING_H
+
+#include <stddef.h>
+
+void *memcpy(void *restrict dest, const void *restrict src, size_t n);
+void *memmove(void *dest, const void *src, size_t n);
+void *memset(void *s, int c, size_t n);
+int memcmp(const void *l, const void *r, size_t n);
+size_t strlen(const char *s);
+char *strcpy(char *restrict dest, const char *restrict src);
+char *strncpy(char *restrict dest, const char *restrict src, size_t n);
+int strcmp(const char *l, const char *r);
+int strncmp(const char *l, const char *r, size_t n);
+
+#endif
@@ -0,0 +1,47 @@
+#include <stddef.h>
+
+#include "ext4_errno.h"
+#include "ext4_fs.h"
+#include "ext4_journal.h"
+
+/*
+ * Journal transactions around inode updates made from Rust. ext4.c keeps
+ * its ext4_trans_start/stop/abort static, and struct ext4_mountpoint
+ * private, so these mirror them on the mount point's struct ext4_fs. The
+ * caller holds the mount point lock.
+ */
+
+int lwext4_rust_trans_start(struct ext4_fs *fs)
+{
+#if CONFIG_JOURNALING_ENABLE
+	if (fs->jbd_journal && !fs->curr_trans) {
+		struct jbd_trans *trans = jbd_journal_new_trans(fs->jbd_journal);
+		if (!trans)
+			return ENOMEM;
+		fs->curr_trans = trans;
+	}
+#endif
+	return EOK;
+}
+
+int lwext4_rust_trans_stop(struct ext4_fs *fs)
+{
+	int r = EOK;
+#if CONFIG_JOURNALING_ENABLE
+	if (fs->jbd_journal && fs->curr_trans) {
+		r = jbd_journal_commit_trans(fs->jbd_journal, fs->curr_trans);
+		fs->curr_trans = NULL;
+	}
+#endif
+	return r;
+}
+
+void lwext4_rust_trans_abort(struct ext4_fs *fs)
+{
+#if CONFIG_JOURNALING_ENABLE
+	if (fs->jbd_journal && fs->curr_trans) {
+		jbd_journal_free_trans(fs->jbd_journal, fs->curr_trans, true);
+		fs->curr_trans = NULL;
+	}
+#endif
+}
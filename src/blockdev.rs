@@ -1,14 +1,23 @@
-use alloc::{boxed::Box, ffi::CString};
+use alloc::{
+    boxed::Box,
+    ffi::CString,
+    string::{String, ToString},
+    vec,
+};
 use core::{
-    ffi::{c_char, c_void},
-    ptr::null_mut,
+    ffi::{c_char, c_int, c_void},
+    mem::ManuallyDrop,
+    ptr::{null_mut, slice_from_raw_parts_mut},
     slice::{from_raw_parts, from_raw_parts_mut},
     str,
 };
 
 use crate::bindings::*;
+use crate::error::{Ext4Error, Ext4ErrorKind, Ext4Result};
+use crate::metadata::Timespec;
+use crate::mount::{self, ClockFn};
 
-/// Device block size.
+/// Default logical sector size of a device.
 const EXT4_DEV_BSIZE: u32 = 512;
 
 pub trait KernelDevOp {
@@ -23,72 +32,353 @@ pub trait KernelDevOp {
     fn flush(dev: &mut Self::DevType) -> Result<usize, i32>
     where
         Self: Sized;
+
+    /// Logical sector size of the device in bytes: 512, 1024, 2048 or 4096.
+    /// lwext4 reads and writes whole sectors, so 4Kn devices should report
+    /// 4096.
+    fn sector_size(_dev: &mut Self::DevType) -> u32 {
+        EXT4_DEV_BSIZE
+    }
+
+    /// Size of the device in bytes. Defaults to seeking to the end.
+    fn size(dev: &mut Self::DevType) -> Result<u64, i32> {
+        Self::seek(dev, 0, SEEK_END as i32).map(|v| v as u64)
+    }
+
+    /// Reads whole sectors starting at sector `lba` into `buf`. Defaults to
+    /// a seek followed by reads until `buf` is full.
+    fn read_blocks(dev: &mut Self::DevType, lba: u64, buf: &mut [u8]) -> Result<(), i32> {
+        let off = lba * Self::sector_size(dev) as u64;
+        Self::seek(dev, off as i64, SEEK_SET as i32)?;
+        let mut done = 0;
+        while done < buf.len() {
+            match Self::read(dev, &mut buf[done..])? {
+                0 => return Err(EIO as i32),
+                n => done += n,
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes whole sectors from `buf` starting at sector `lba`. Defaults to
+    /// a seek followed by writes until `buf` is consumed.
+    fn write_blocks(dev: &mut Self::DevType, lba: u64, buf: &[u8]) -> Result<(), i32> {
+        let off = lba * Self::sector_size(dev) as u64;
+        Self::seek(dev, off as i64, SEEK_SET as i32)?;
+        let mut done = 0;
+        while done < buf.len() {
+            match Self::write(dev, &buf[done..])? {
+                0 => return Err(EIO as i32),
+                n => done += n,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A block device addressed by sector, for drivers that have no notion of a
+/// byte cursor.
+///
+/// Every `BlockDevice` is also a [`KernelDevOp`] whose device type is itself,
+/// so it can be passed wherever one is expected:
+///
+/// ```ignore
+/// let fs = Ext4BlockWrapper::<NvmeDisk>::new(nvme)?;
+/// ```
+///
+/// The byte-stream methods `read`, `write` and `seek` of that implementation
+/// fail with `ENOTSUP`; this crate only calls the positional ones.
+pub trait BlockDevice {
+    /// Reads `count` sectors starting at `lba` into `buf`, which is
+    /// `count * block_size()` bytes long.
+    fn read_blocks(&mut self, lba: u64, count: u32, buf: &mut [u8]) -> Result<(), i32>;
+    /// Writes `count` sectors from `buf` starting at `lba`.
+    fn write_blocks(&mut self, lba: u64, count: u32, buf: &[u8]) -> Result<(), i32>;
+    fn flush(&mut self) -> Result<(), i32>;
+    /// Number of sectors of the device.
+    fn num_blocks(&mut self) -> u64;
+    /// Sector size in bytes, see [`KernelDevOp::sector_size`].
+    fn block_size(&mut self) -> u32;
+}
+
+impl<D: BlockDevice> KernelDevOp for D {
+    type DevType = D;
+
+    fn write(_dev: &mut D, _buf: &[u8]) -> Result<usize, i32> {
+        Err(ENOTSUP as i32)
+    }
+
+    fn read(_dev: &mut D, _buf: &mut [u8]) -> Result<usize, i32> {
+        Err(ENOTSUP as i32)
+    }
+
+    fn seek(_dev: &mut D, _off: i64, _whence: i32) -> Result<i64, i32> {
+        Err(ENOTSUP as i32)
+    }
+
+    fn flush(dev: &mut D) -> Result<usize, i32> {
+        BlockDevice::flush(dev).map(|_| 0)
+    }
+
+    fn sector_size(dev: &mut D) -> u32 {
+        dev.block_size()
+    }
+
+    fn size(dev: &mut D) -> Result<u64, i32> {
+        Ok(dev.num_blocks() * dev.block_size() as u64)
+    }
+
+    fn read_blocks(dev: &mut D, lba: u64, buf: &mut [u8]) -> Result<(), i32> {
+        let count = buf.len() / dev.block_size() as usize;
+        BlockDevice::read_blocks(dev, lba, count as u32, buf)
+    }
+
+    fn write_blocks(dev: &mut D, lba: u64, buf: &[u8]) -> Result<(), i32> {
+        let count = buf.len() / dev.block_size() as usize;
+        BlockDevice::write_blocks(dev, lba, count as u32, buf)
+    }
+}
+
+/// Lock routines of the OS, installed at mount time with
+/// [`MountOptions::locking`] so lwext4 can be called from several cores.
+///
+/// The callbacks carry no context, so one lock of the implementing type is
+/// shared by every volume mounted with it. They are installed after
+/// `ext4_mount`, so they do not cover registering, mounting and unmounting;
+/// the crate serializes those itself.
+pub trait Ext4Locking {
+    /// Locks access to the mount point, around every filesystem call.
+    fn lock();
+    fn unlock();
+
+    /// Locks the block device around raw block I/O. This is taken while the
+    /// mount point lock is held, so it must not be the same non-reentrant
+    /// lock. Does nothing by default.
+    fn lock_device() {}
+    fn unlock_device() {}
+}
+
+unsafe extern "C" fn mp_lock<L: Ext4Locking>() {
+    L::lock()
+}
+
+unsafe extern "C" fn mp_unlock<L: Ext4Locking>() {
+    L::unlock()
+}
+
+unsafe extern "C" fn dev_lock<L: Ext4Locking>(_bdev: *mut ext4_blockdev) -> c_int {
+    L::lock_device();
+    EOK as _
+}
+
+unsafe extern "C" fn dev_unlock<L: Ext4Locking>(_bdev: *mut ext4_blockdev) -> c_int {
+    L::unlock_device();
+    EOK as _
+}
+
+type DevLockFn = unsafe extern "C" fn(*mut ext4_blockdev) -> c_int;
+
+/// Options used by [`Ext4BlockWrapper::new_with_options`] to mount a volume.
+///
+/// The defaults match [`Ext4BlockWrapper::new`]: read-write at `/`, journal
+/// recovery (with the `journal` feature) but no journaling, and write-back
+/// cache only with the `write-back` feature.
+#[derive(Debug, Clone)]
+pub struct MountOptions {
+    read_only: bool,
+    recover_journal: bool,
+    enable_journal: bool,
+    write_back: bool,
+    debug_mask: u32,
+    mount_point: String,
+    locks: Option<ext4_lock>,
+    dev_locks: Option<(DevLockFn, DevLockFn)>,
+    clock: Option<ClockFn>,
+}
+
+impl Default for MountOptions {
+    fn default() -> Self {
+        Self {
+            read_only: false,
+            recover_journal: cfg!(feature = "journal"),
+            enable_journal: false,
+            write_back: cfg!(feature = "write-back"),
+            debug_mask: DEBUG_ALL,
+            mount_point: "/".to_string(),
+            locks: None,
+            dev_locks: None,
+            clock: None,
+        }
+    }
+}
+
+impl MountOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Mount the volume read-only. Journaling is never started on a
+    /// read-only mount.
+    pub fn read_only(mut self, on: bool) -> Self {
+        self.read_only = on;
+        self
+    }
+
+    /// Replay the journal with `ext4_recover` right after mounting, so an
+    /// unclean volume is made consistent. Skipped on read-only mounts.
+    pub fn recover_journal(mut self, on: bool) -> Self {
+        self.recover_journal = on;
+        self
+    }
+
+    /// Start journaling with `ext4_journal_start` after mounting, and stop
+    /// it on unmount.
+    pub fn enable_journal(mut self, on: bool) -> Self {
+        self.enable_journal = on;
+        self
+    }
+
+    /// Enable the write-back mode of the block cache.
+    pub fn write_back(mut self, on: bool) -> Self {
+        self.write_back = on;
+        self
+    }
+
+    /// Debug mask passed to `ext4_dmask_set`, e.g. `DEBUG_ALL`.
+    pub fn debug_mask(mut self, mask: u32) -> Self {
+        self.debug_mask = mask;
+        self
+    }
+
+    /// Mount point name, which must start and end with `/`, e.g. `/data/`.
+    /// Paths passed to [`Ext4File`](crate::Ext4File), [`Ext4Dir`](crate::Ext4Dir)
+    /// and the `lwext4_*` functions are routed to the volume with the
+    /// longest matching mount point.
+    pub fn mount_point(mut self, mount_point: &str) -> Self {
+        self.mount_point = mount_point.to_string();
+        self
+    }
+
+    /// Install the lock routines of `L` for the mount point and the block
+    /// device. Without them lwext4 must only be used from one core at a time.
+    pub fn locking<L: Ext4Locking>(mut self) -> Self {
+        self.locks = Some(ext4_lock {
+            lock: Some(mp_lock::<L>),
+            unlock: Some(mp_unlock::<L>),
+        });
+        self.dev_locks = Some((dev_lock::<L>, dev_unlock::<L>));
+        self
+    }
+
+    /// Clock giving the current time, used for the change time set by
+    /// `set_permissions`/`set_owner` and for [`SetTime::Now`]. lwext4 has no
+    /// clock of its own, so without one these timestamps are left alone.
+    ///
+    /// [`SetTime::Now`]: crate::SetTime::Now
+    pub fn clock(mut self, now: fn() -> Timespec) -> Self {
+        self.clock = Some(now);
+        self
+    }
+
+    pub fn get_mount_point(&self) -> &str {
+        &self.mount_point
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
 }
 
 pub struct Ext4BlockWrapper<K: KernelDevOp> {
-    value: Box<ext4_blockdev>,
+    /// Released by hand in `drop`, and only once lwext4 let go of it.
+    value: ManuallyDrop<Box<ext4_blockdev>>,
+    /// Block cache allocated with `value`. `ext4_mount` points `value.bc` at
+    /// the cache of the mount point instead, so this is what gets freed.
+    bc: *mut ext4_bcache,
     // block_dev: K::DevType,
-    name: [u8; 16],
+    /// Id of this volume in the mount table.
+    id: usize,
+    name: [u8; 32],
     mount_point: [u8; 32],
+    options: MountOptions,
+    /// Lock routines handed to `ext4_mount_setup_locks`, which keeps a
+    /// pointer to them while mounted.
+    locks: Option<Box<ext4_lock>>,
+    registered: bool,
+    mounted: bool,
+    journal_started: bool,
     pd: core::marker::PhantomData<K>,
 }
 
 impl<K: KernelDevOp> Ext4BlockWrapper<K> {
-    pub fn new(block_dev: K::DevType) -> Result<Self, i32> {
+    pub fn new(block_dev: K::DevType) -> Ext4Result<Self> {
+        Self::new_with_options(block_dev, MountOptions::default())
+    }
+
+    pub fn new_with_options(block_dev: K::DevType, options: MountOptions) -> Ext4Result<Self> {
+        Self::new_partition(block_dev, 0, 0, options)
+    }
+
+    /// Mounts the partition of `block_dev` starting at byte `part_offset`
+    /// and `part_size` bytes long, e.g. one found by
+    /// [`mbr_scan`](crate::mbr::mbr_scan). A `part_size` of 0 stands for the
+    /// whole device.
+    pub fn new_partition(
+        mut block_dev: K::DevType,
+        part_offset: u64,
+        part_size: u64,
+        options: MountOptions,
+    ) -> Ext4Result<Self> {
+        let mp = options.mount_point.as_str();
+        if !mp.starts_with('/') || !mp.ends_with('/') {
+            error!("invalid mount point: {}", mp);
+            return Err(
+                Ext4Error::from_kind(Ext4ErrorKind::InvalidInput, "ext4_mount").with_path(mp),
+            );
+        }
+        let bsize = Self::sector_size(&mut block_dev)?;
+        let id = mount::register(mp, Self::dev_flush, options.clock)?;
+
         // note this ownership
         let devt_user = Box::into_raw(Box::new(block_dev)) as *mut c_void;
         // let devt_user = devt.as_mut() as *mut _ as *mut c_void;
         // let devt_user = &mut block_dev as *mut _ as *mut c_void;
 
-        // Block size buffer
-        let bbuf = Box::new([0u8; EXT4_DEV_BSIZE as usize]);
-
-        let ext4bdif: ext4_blockdev_iface = ext4_blockdev_iface {
-            open: Some(Self::dev_open),
-            bread: Some(Self::dev_bread),
-            bwrite: Some(Self::dev_bwrite),
-            close: Some(Self::dev_close),
-            lock: None,
-            unlock: None,
-            ph_bsize: EXT4_DEV_BSIZE,
-            ph_bcnt: 0,
-            ph_bbuf: Box::into_raw(bbuf) as *mut u8,
-            ph_refctr: 0,
-            bread_ctr: 0,
-            bwrite_ctr: 0,
-            p_user: devt_user,
-        };
-
-        let bcbuf: Box<ext4_bcache> = Box::new(unsafe { core::mem::zeroed() });
-
-        let ext4dev = ext4_blockdev {
-            bdif: Box::into_raw(Box::new(ext4bdif)),
-            part_offset: 0,
-            part_size: 0 * EXT4_DEV_BSIZE as u64,
-            bc: Box::into_raw(bcbuf),
-            lg_bsize: 0,
-            lg_bcnt: 0,
-            cache_write_back: 0,
-            fs: null_mut(),
-            journal: null_mut(),
-        };
+        let mut ext4dev = Self::alloc_blockdev(devt_user, bsize);
+        ext4dev.part_offset = part_offset;
+        ext4dev.part_size = part_size;
+        if let Some((lock, unlock)) = options.dev_locks {
+            unsafe {
+                (*ext4dev.bdif).lock = Some(lock);
+                (*ext4dev.bdif).unlock = Some(unlock);
+            }
+        }
 
-        let c_name = CString::new("ext4_fs").expect("CString::new ext4_fs failed");
+        // Unique per volume, so several wrappers can be mounted at once.
+        let c_name = CString::new(mount::device_name(id)).expect("CString::new ext4_fs failed");
         let c_name = c_name.as_bytes_with_nul(); // + '\0'
-                                                 // let c_mountpoint = CString::new("/mp/").unwrap();
-        let c_mountpoint = CString::new("/").unwrap();
+        let c_mountpoint = CString::new(mount::internal_mount_point(id)).unwrap();
         let c_mountpoint = c_mountpoint.as_bytes_with_nul();
 
-        let mut name: [u8; 16] = [0; 16];
+        // 32 bytes hold the names for any `usize` id, and are what lwext4
+        // accepts (`CONFIG_EXT4_MAX_BLOCKDEV_NAME`, `CONFIG_EXT4_MAX_MP_NAME`).
+        let mut name: [u8; 32] = [0; 32];
         let mut mount_point: [u8; 32] = [0; 32];
         name[..c_name.len()].copy_from_slice(c_name);
         mount_point[..c_mountpoint.len()].copy_from_slice(c_mountpoint);
 
         let mut ext4bd = Self {
-            value: Box::new(ext4dev),
+            bc: ext4dev.bc,
+            value: ManuallyDrop::new(Box::new(ext4dev)),
             // block_dev,
+            id,
             name,
             mount_point,
+            locks: options.locks.map(Box::new),
+            options,
+            registered: false,
+            mounted: false,
+            journal_started: false,
             pd: core::marker::PhantomData,
         };
 
@@ -98,10 +388,12 @@ impl<K: KernelDevOp> Ext4BlockWrapper<K> {
         // ext4_blockdev into static instance
         // lwext4_mount
         // let c_mountpoint = c_mountpoint as *const _ as *const c_char;
+        // On failure `ext4bd` is dropped, which releases the device and the
+        // buffers handed to lwext4 above.
         unsafe {
-            ext4bd
-                .lwext4_mount()
-                .expect("Failed to mount the ext4 file system, perhaps the disk is not an EXT4 file system.");
+            ext4bd.lwext4_mount().inspect_err(|_| {
+                warn!("Failed to mount the ext4 file system, perhaps the disk is not an EXT4 file system.");
+            })?;
         }
 
         ext4bd.lwext4_dir_ls();
@@ -110,6 +402,76 @@ impl<K: KernelDevOp> Ext4BlockWrapper<K> {
 
         Ok(ext4bd)
     }
+
+    /// Mount point this volume was mounted at, e.g. `/data/`.
+    pub fn mount_point(&self) -> &str {
+        self.options.get_mount_point()
+    }
+
+    /// Returns the sector size reported by [`KernelDevOp::sector_size`],
+    /// checking that lwext4 can use it.
+    pub(crate) fn sector_size(block_dev: &mut K::DevType) -> Ext4Result<u32> {
+        let bsize = K::sector_size(block_dev);
+        if !bsize.is_power_of_two() || !(512..=4096).contains(&bsize) {
+            error!("unsupported sector size: {}", bsize);
+            return Err(Ext4Error::from_kind(
+                Ext4ErrorKind::Unsupported,
+                "ext4_device_register",
+            ));
+        }
+        Ok(bsize)
+    }
+
+    /// Allocates the lwext4 block device structures for the device behind
+    /// `devt_user`, with sectors of `bsize` bytes. They are released by
+    /// [`Self::free_blockdev`], given the `bc` set here: lwext4 rebinds
+    /// `bc` to a cache of its own in `ext4_mount` and `ext4_mkfs`.
+    pub(crate) fn alloc_blockdev(devt_user: *mut c_void, bsize: u32) -> ext4_blockdev {
+        // Block size buffer
+        let bbuf: Box<[u8]> = vec![0u8; bsize as usize].into_boxed_slice();
+
+        let ext4bdif: ext4_blockdev_iface = ext4_blockdev_iface {
+            open: Some(Self::dev_open),
+            bread: Some(Self::dev_bread),
+            bwrite: Some(Self::dev_bwrite),
+            close: Some(Self::dev_close),
+            lock: None,
+            unlock: None,
+            ph_bsize: bsize,
+            ph_bcnt: 0,
+            ph_bbuf: Box::into_raw(bbuf) as *mut u8,
+            ph_refctr: 0,
+            bread_ctr: 0,
+            bwrite_ctr: 0,
+            p_user: devt_user,
+        };
+
+        let bcbuf: Box<ext4_bcache> = Box::new(unsafe { core::mem::zeroed() });
+
+        ext4_blockdev {
+            bdif: Box::into_raw(Box::new(ext4bdif)),
+            part_offset: 0,
+            part_size: 0,
+            bc: Box::into_raw(bcbuf),
+            lg_bsize: 0,
+            lg_bcnt: 0,
+            cache_write_back: 0,
+            fs: null_mut(),
+            journal: null_mut(),
+        }
+    }
+
+    /// Releases what [`Self::alloc_blockdev`] allocated, with `bc` the block
+    /// cache it set. The device behind `p_user` is left alone.
+    pub(crate) unsafe fn free_blockdev(bdev: &ext4_blockdev, bc: *mut ext4_bcache) {
+        let bdif = Box::from_raw(bdev.bdif);
+        drop(Box::from_raw(slice_from_raw_parts_mut(
+            bdif.ph_bbuf,
+            bdif.ph_bsize as usize,
+        )));
+        drop(Box::from_raw(bc));
+    }
+
     pub unsafe extern "C" fn dev_open(bdev: *mut ext4_blockdev) -> ::core::ffi::c_int {
         let p_user = (*(*bdev).bdif).p_user;
         debug!("OPEN Ext4 block device p_user={:#x}", p_user as usize);
@@ -124,18 +486,20 @@ impl<K: KernelDevOp> Ext4BlockWrapper<K> {
         // buffering at Disk
         // setbuf(dev_file, buffer);
 
-        let seek_off = K::seek(devt, 0, SEEK_END as i32);
-        let cur = match seek_off {
+        let cur = match K::size(devt) {
             Ok(v) => v,
             Err(e) => {
-                error!("dev_open to K::seek failed: {:?}", e);
+                error!("dev_open to K::size failed: {:?}", e);
                 return EFAULT as _;
             }
         };
 
-        (*bdev).part_offset = 0;
-        (*bdev).part_size = cur as u64; // ftello()
-        (*(*bdev).bdif).ph_bcnt = (*bdev).part_size / (*(*bdev).bdif).ph_bsize as u64;
+        // A partition keeps the geometry it was set up with.
+        if (*bdev).part_size == 0 {
+            (*bdev).part_offset = 0;
+            (*bdev).part_size = cur as u64; // ftello()
+        }
+        (*(*bdev).bdif).ph_bcnt = cur as u64 / (*(*bdev).bdif).ph_bsize as u64;
         EOK as _
     }
     pub unsafe extern "C" fn dev_bread(
@@ -147,30 +511,17 @@ impl<K: KernelDevOp> Ext4BlockWrapper<K> {
         debug!("READ Ext4 block id: {}, count: {}", blk_id, blk_cnt);
         let devt = unsafe { &mut *((*(*bdev).bdif).p_user as *mut K::DevType) };
 
-        let seek_off = K::seek(
-            devt,
-            (blk_id * ((*(*bdev).bdif).ph_bsize as u64)) as i64,
-            SEEK_SET as i32,
-        );
-        match seek_off {
-            Ok(v) => v,
-            Err(_e) => return EIO as _,
-        };
-
         if blk_cnt == 0 {
             return EOK as _;
         }
 
-        let buf_len = ((*(*bdev).bdif).ph_bsize * blk_cnt * 1) as usize;
+        let buf_len = ((*(*bdev).bdif).ph_bsize * blk_cnt) as usize;
         let buffer = unsafe { from_raw_parts_mut(buf as *mut u8, buf_len) };
 
-        let read_cnt = K::read(devt, buffer);
-        match read_cnt {
-            Ok(v) => v,
-            Err(_e) => return EIO as _,
-        };
-
-        EOK as _
+        match K::read_blocks(devt, blk_id, buffer) {
+            Ok(_) => EOK as _,
+            Err(_e) => EIO as _,
+        }
     }
     pub unsafe extern "C" fn dev_bwrite(
         bdev: *mut ext4_blockdev,
@@ -181,109 +532,234 @@ impl<K: KernelDevOp> Ext4BlockWrapper<K> {
         debug!("WRITE Ext4 block id: {}, count: {}", blk_id, blk_cnt);
 
         let devt = unsafe { &mut *((*(*bdev).bdif).p_user as *mut K::DevType) };
-        // let mut devt = unsafe { K::DevType::borrow_mut((*(*bdev).bdif).p_user) };
-        // let mut devt = unsafe { K::DevType::from_foreign((*(*bdev).bdif).p_user) };
-        // let mut devt = Box::from_raw((*(*bdev).bdif).p_user as *mut K::DevType);
-
-        let seek_off = K::seek(
-            devt,
-            (blk_id * ((*(*bdev).bdif).ph_bsize as u64)) as i64,
-            SEEK_SET as i32,
-        );
-        match seek_off {
-            Ok(v) => v,
-            Err(_e) => return EIO as _,
-        };
 
         if blk_cnt == 0 {
             return EOK as _;
         }
 
-        let buf_len = ((*(*bdev).bdif).ph_bsize * blk_cnt * 1) as usize;
+        let buf_len = ((*(*bdev).bdif).ph_bsize * blk_cnt) as usize;
         let buffer = unsafe { from_raw_parts(buf as *const u8, buf_len) };
-        let write_cnt = K::write(devt, buffer);
-        match write_cnt {
-            Ok(v) => v,
-            Err(_e) => return EIO as _,
-        };
 
         // drop_cache();
         // sync
-
-        EOK as _
+        match K::write_blocks(devt, blk_id, buffer) {
+            Ok(_) => EOK as _,
+            Err(_e) => EIO as _,
+        }
     }
+    /// Drains the write cache of the device behind `bdev` with
+    /// [`KernelDevOp::flush`].
+    pub(crate) unsafe fn dev_flush(bdev: *mut ext4_blockdev) -> i32 {
+        let devt = unsafe { &mut *((*(*bdev).bdif).p_user as *mut K::DevType) };
+        match K::flush(devt) {
+            Ok(_) => EOK as _,
+            Err(e) => {
+                error!("K::flush failed: {}", e);
+                EIO as _
+            }
+        }
+    }
+
     pub unsafe extern "C" fn dev_close(_bdev: *mut ext4_blockdev) -> ::core::ffi::c_int {
         debug!("CLOSE Ext4 block device");
         // fclose(dev_file);
         EOK as _
     }
 
-    pub unsafe fn lwext4_mount(&mut self) -> Result<usize, i32> {
+    pub unsafe fn lwext4_mount(&mut self) -> Ext4Result<usize> {
+        let c_mountpoint = &self.mount_point as *const _ as *const c_char;
+
+        if let Err(e) = self.register_and_mount() {
+            let _ = self.lwext4_umount();
+            return Err(e);
+        }
+
+        if self.options.recover_journal && !self.options.read_only {
+            if let Err(e) = self.recover() {
+                let _ = self.lwext4_umount();
+                return Err(e);
+            }
+        }
+
+        if self.options.enable_journal && !self.options.read_only {
+            if let Err(e) = self.journal_start() {
+                let _ = self.lwext4_umount();
+                return Err(e);
+            }
+        }
+
+        ext4_cache_write_back(c_mountpoint, self.options.write_back);
+        // ext4_bcache
+
+        info!("lwext4 mount Okay");
+        Ok(0)
+    }
+
+    /// Registers the device and mounts it, holding the table lock so no other
+    /// volume is registered or unmounted meanwhile. Cleaning up after a
+    /// failure is left to the caller.
+    unsafe fn register_and_mount(&mut self) -> Ext4Result<()> {
         let c_name = &self.name as *const _ as *const c_char;
         let c_mountpoint = &self.mount_point as *const _ as *const c_char;
+        let _tables = mount::lock_tables();
 
         let r = ext4_device_register(self.value.as_mut(), c_name);
         if r != EOK as i32 {
             error!("ext4_device_register: rc = {:?}\n", r);
-            return Err(r);
+            return Err(Ext4Error::new(r, "ext4_device_register"));
         }
-        let r = ext4_mount(c_name, c_mountpoint, false);
+        self.registered = true;
+
+        let r = ext4_mount(c_name, c_mountpoint, self.options.read_only);
         if r != EOK as i32 {
             error!("ext4_mount: rc = {:?}\n", r);
-            return Err(r);
-        }
-        // let r = ext4_recover(c_mountpoint);
-        // if (r != EOK as i32) && (r != ENOTSUP as i32) {
-        //     error!("ext4_recover: rc = {:?}\n", r);
-        //     return Err(r);
-        // }
-
-        //  ext4_mount("sda1", "/");
-        //  ext4_journal_start("/");
-        //
-        // File operations here...
-        //
-        //  ext4_journal_stop("/");
-        //  ext4_umount("/");
-        // let r = ext4_journal_start(c_mountpoint);
-        // if r != EOK as i32 {
-        //     error!("ext4_journal_start: rc = {:?}\n", r);
-        //     return Err(r);
-        // }
-        #[cfg(feature = "write-back")]
-        ext4_cache_write_back(c_mountpoint, true);
-        #[cfg(not(feature = "write-back"))]
-        ext4_cache_write_back(c_mountpoint, false);
-        // ext4_bcache
+            return Err(Ext4Error::new(r, "ext4_mount"));
+        }
+        self.mounted = true;
 
-        info!("lwext4 mount Okay");
-        Ok(0)
+        if let Some(locks) = &self.locks {
+            let r = ext4_mount_setup_locks(c_mountpoint, locks.as_ref());
+            if r != EOK as i32 {
+                error!("ext4_mount_setup_locks: rc = {:?}\n", r);
+                return Err(Ext4Error::new(r, "ext4_mount_setup_locks"));
+            }
+        }
+        Ok(())
     }
 
-    /// Call this when block device is being uninstalled
-    pub fn lwext4_umount(&mut self) -> Result<usize, i32> {
-        let c_name = &self.name as *const _ as *const c_char;
+    /// Makes everything written so far durable: dirty buffers of the lwext4
+    /// block cache are written to the device with `ext4_cache_flush`, and
+    /// only once those writes have completed is the device's own write cache
+    /// drained with [`KernelDevOp::flush`].
+    pub fn sync(&self) -> Ext4Result<()> {
         let c_mountpoint = &self.mount_point as *const _ as *const c_char;
+        let r = unsafe { ext4_cache_flush(c_mountpoint) };
+        if r != EOK as i32 {
+            error!("ext4_cache_flush: rc = {:?}\n", r);
+            return Err(Ext4Error::new(r, "ext4_cache_flush"));
+        }
+        let bdev = self.value.as_ref() as *const ext4_blockdev as *mut ext4_blockdev;
+        let r = unsafe { Self::dev_flush(bdev) };
+        if r != EOK as i32 {
+            return Err(Ext4Error::new(r, "ext4_blockdev_flush"));
+        }
+        Ok(())
+    }
 
+    /// Replays the journal of the volume with `ext4_recover`, e.g. after a
+    /// crash left it unclean. A volume without a journal is left as is.
+    /// Fails with [`Ext4ErrorKind::Unsupported`] without the `journal` feature.
+    pub fn recover(&mut self) -> Ext4Result<()> {
+        if !cfg!(feature = "journal") {
+            return Err(Ext4Error::from_kind(
+                Ext4ErrorKind::Unsupported,
+                "ext4_recover",
+            ));
+        }
+        let c_mountpoint = &self.mount_point as *const _ as *const c_char;
+        let r = unsafe { ext4_recover(c_mountpoint) };
+        if r != EOK as i32 && r != ENOTSUP as i32 {
+            error!("ext4_recover: rc = {:?}\n", r);
+            return Err(Ext4Error::new(r, "ext4_recover"));
+        }
+        Ok(())
+    }
+
+    /// Starts journaling with `ext4_journal_start`, so metadata updates are
+    /// written through the journal. Does nothing if already started.
+    /// Fails with [`Ext4ErrorKind::Unsupported`] without the `journal` feature.
+    pub fn journal_start(&mut self) -> Ext4Result<()> {
+        if self.journal_started {
+            return Ok(());
+        }
+        if !cfg!(feature = "journal") {
+            return Err(Ext4Error::from_kind(
+                Ext4ErrorKind::Unsupported,
+                "ext4_journal_start",
+            ));
+        }
+        if self.options.read_only {
+            return Err(Ext4Error::from_kind(
+                Ext4ErrorKind::ReadOnly,
+                "ext4_journal_start",
+            ));
+        }
+        let c_mountpoint = &self.mount_point as *const _ as *const c_char;
+        let r = unsafe { ext4_journal_start(c_mountpoint) };
+        if r != EOK as i32 {
+            error!("ext4_journal_start: rc = {:?}\n", r);
+            return Err(Ext4Error::new(r, "ext4_journal_start"));
+        }
+        self.journal_started = true;
+        Ok(())
+    }
+
+    /// Stops journaling with `ext4_journal_stop`, which commits and
+    /// checkpoints the pending transactions. Does nothing if not started.
+    pub fn journal_stop(&mut self) -> Ext4Result<()> {
+        if !self.journal_started {
+            return Ok(());
+        }
+        let c_mountpoint = &self.mount_point as *const _ as *const c_char;
+        let r = unsafe { ext4_journal_stop(c_mountpoint) };
+        if r != EOK as i32 {
+            error!("ext4_journal_stop: fail {}", r);
+            return Err(Ext4Error::new(r, "ext4_journal_stop"));
+        }
+        self.journal_started = false;
+        Ok(())
+    }
+
+    pub fn is_journal_started(&self) -> bool {
+        self.journal_started
+    }
+
+    /// Unmounts the volume and hands the device back, e.g. to keep the
+    /// contents of a [`RamDisk`](crate::RamDisk) or mount it again.
+    pub fn into_device(mut self) -> Ext4Result<K::DevType> {
+        self.lwext4_umount()?;
         unsafe {
-            ext4_cache_write_back(c_mountpoint, false);
+            let devt = Box::from_raw((*self.value.bdif).p_user as *mut K::DevType);
+            (*self.value.bdif).p_user = null_mut();
+            Ok(*devt)
+        }
+    }
 
-            let r = ext4_journal_stop(c_mountpoint);
-            if r != EOK as i32 {
-                error!("ext4_journal_stop: fail {}", r);
-                return Err(r);
-            }
+    /// Call this when block device is being uninstalled
+    pub fn lwext4_umount(&mut self) -> Ext4Result<usize> {
+        let c_name = &self.name as *const _ as *const c_char;
+        let c_mountpoint = &self.mount_point as *const _ as *const c_char;
 
-            let r = ext4_umount(c_mountpoint);
-            if r != EOK as i32 {
-                error!("ext4_umount: fail {}", r);
-                return Err(r);
+        unsafe {
+            if self.mounted {
+                ext4_cache_write_back(c_mountpoint, false);
+
+                self.journal_stop()?;
+
+                let tables = mount::lock_tables();
+                let r = ext4_umount(c_mountpoint);
+                drop(tables);
+                if r != EOK as i32 {
+                    error!("ext4_umount: fail {}", r);
+                    return Err(Ext4Error::new(r, "ext4_umount"));
+                }
+                self.mounted = false;
+
+                // ext4_umount wrote back the block cache, drain the device.
+                let r = Self::dev_flush(self.value.as_mut());
+                if r != EOK as i32 {
+                    return Err(Ext4Error::new(r, "ext4_blockdev_flush"));
+                }
             }
 
-            let r = ext4_device_unregister(c_name);
-            if r != EOK as i32 {
-                error!("ext4_device_unregister: fail {}", r);
-                return Err(r);
+            if self.registered {
+                let _tables = mount::lock_tables();
+                let r = ext4_device_unregister(c_name);
+                if r != EOK as i32 {
+                    error!("ext4_device_unregister: fail {}", r);
+                    return Err(Ext4Error::new(r, "ext4_device_unregister"));
+                }
+                self.registered = false;
             }
         }
 
@@ -331,7 +807,7 @@ impl<K: KernelDevOp> Ext4BlockWrapper<K> {
 
     pub fn ext4_set_debug(&self) {
         unsafe {
-            ext4_dmask_set(DEBUG_ALL);
+            ext4_dmask_set(self.options.debug_mask);
         }
     }
 
@@ -385,8 +861,27 @@ impl<K: KernelDevOp> Ext4BlockWrapper<K> {
 impl<K: KernelDevOp> Drop for Ext4BlockWrapper<K> {
     fn drop(&mut self) {
         info!("Drop struct Ext4BlockWrapper");
-        self.lwext4_umount().unwrap();
-        let devtype = unsafe { Box::from_raw((*(&self.value).bdif).p_user as *mut K::DevType) };
-        drop(devtype);
+        if let Err(e) = self.lwext4_umount() {
+            error!("Failed to unmount on drop: {}", e);
+        }
+        if self.mounted || self.registered {
+            // lwext4 still points at the device, its buffers and the lock
+            // routines: leak them rather than leave its tables dangling, and
+            // keep the volume id taken.
+            error!("leaking the block device of {}", self.mount_point());
+            core::mem::forget(self.locks.take());
+            return;
+        }
+        // Release what `new_partition` handed over to lwext4, unless
+        // `into_device` took the device back.
+        unsafe {
+            let p_user = (*self.value.bdif).p_user;
+            if !p_user.is_null() {
+                drop(Box::from_raw(p_user as *mut K::DevType));
+            }
+            Self::free_blockdev(&self.value, self.bc);
+            ManuallyDrop::drop(&mut self.value);
+        }
+        mount::unregister(self.id);
     }
 }
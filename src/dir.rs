@@ -1,80 +1,120 @@
-use alloc::{ffi::CString, vec::Vec};
+use alloc::vec::Vec;
 use core::mem::MaybeUninit;
 
 use crate::{
-    bindings::{ext4_dir, ext4_dir_close, ext4_dir_entry_next, ext4_dir_mk, ext4_dir_open},
+    bindings::{
+        ext4_dir, ext4_dir_close, ext4_dir_entry_next, ext4_dir_entry_rewind, ext4_dir_mk,
+        ext4_dir_open,
+    },
+    error::{Ext4Error, Ext4ErrorKind, Ext4Result},
+    mount::resolve_path,
     InodeTypes,
 };
 
-pub struct Ext4Dir(ext4_dir);
+/// `next_off` of a directory whose last entry has been read
+/// (`EXT4_DIR_ENTRY_OFFSET_TERM`).
+const DIR_ENTRY_OFFSET_TERM: u64 = u64::MAX;
+
+/// An open directory, iterating over its entries.
+pub struct Ext4Dir {
+    dir: ext4_dir,
+    skip_dots: bool,
+    /// Set after the end or an error, until [`Ext4Dir::rewind`].
+    done: bool,
+}
 
 impl Drop for Ext4Dir {
     fn drop(&mut self) {
         unsafe {
-            ext4_dir_close(&mut self.0);
+            ext4_dir_close(&mut self.dir);
         }
     }
 }
 
-pub struct Ext4DirEntry<'a> {
+/// An entry read from an [`Ext4Dir`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct DirEntry {
     pub inode: u32,
-    pub name: &'a str,
-    pub type_: u8,
+    /// Name as stored on disk, which need not be UTF-8.
+    pub name: Vec<u8>,
+    /// One of the `EXT4_INODE_MODE_*` types, as in
+    /// [`Metadata::file_type`](crate::Metadata::file_type), or
+    /// `EXT4_DE_UNKNOWN` on volumes that do not record the type in directory
+    /// entries.
+    pub file_type: InodeTypes,
+}
+
+impl DirEntry {
+    /// The name, if it is valid UTF-8.
+    pub fn name_str(&self) -> Option<&str> {
+        core::str::from_utf8(&self.name).ok()
+    }
+
+    /// Whether this is the `.` or `..` entry.
+    pub fn is_dot(&self) -> bool {
+        self.name == b"." || self.name == b".."
+    }
 }
 
 impl Ext4Dir {
-    pub fn open(path: &str) -> Result<Self, i32> {
-        let c_path = CString::new(path).expect("CString::new failed");
+    pub fn open(path: &str) -> Ext4Result<Self> {
+        let c_path = resolve_path(path)?;
         let mut dir = MaybeUninit::uninit();
         let r = unsafe { ext4_dir_open(dir.as_mut_ptr(), c_path.as_ptr()) };
         match r {
-            0 => unsafe { Ok(Self(dir.assume_init())) },
+            0 => unsafe { Ok(Self::from_raw(dir.assume_init())) },
             e => {
                 error!("ext4_dir_open: {}, rc = {}", path, r);
-                Err(e)
+                Err(Ext4Error::new(e, "ext4_dir_open").with_path(path))
             }
         }
     }
 
-    pub fn create(path: &str) -> Result<Self, i32> {
-        let c_path = CString::new(path).expect("CString::new failed");
+    pub fn create(path: &str) -> Ext4Result<Self> {
+        let c_path = resolve_path(path)?;
         let r = unsafe { ext4_dir_mk(c_path.as_ptr()) };
         match r {
             0 => {}
             e => {
                 error!("ext4_dir_mk: {}, rc = {}", path, r);
-                return Err(e);
+                return Err(Ext4Error::new(e, "ext4_dir_mk").with_path(path));
             }
         }
         let mut dir = MaybeUninit::uninit();
         let r = unsafe { ext4_dir_open(dir.as_mut_ptr(), c_path.as_ptr()) };
         match r {
-            0 => unsafe { Ok(Self(dir.assume_init())) },
+            0 => unsafe { Ok(Self::from_raw(dir.assume_init())) },
             e => {
                 error!("ext4_dir_open: {}, rc = {}", path, r);
-                Err(e)
+                Err(Ext4Error::new(e, "ext4_dir_open").with_path(path))
             }
         }
     }
 }
 
 impl Ext4Dir {
-    pub fn next(&mut self) -> Option<Ext4DirEntry> {
-        unsafe {
-            if ext4_dir_entry_next(&mut self.0).is_null() {
-                return None;
-            }
-        };
-        let name_buf = &self.0.de.name[..self.0.de.name_length as usize];
-        Some(Ext4DirEntry {
-            inode: self.0.de.inode,
-            name: core::str::from_utf8(&name_buf).unwrap(),
-            type_: self.0.de.inode_type,
-        })
+    fn from_raw(dir: ext4_dir) -> Self {
+        Self {
+            dir,
+            skip_dots: false,
+            done: false,
+        }
     }
 
-    pub fn lwext4_dir_entries(&self, path: &str) -> Result<(Vec<Vec<u8>>, Vec<InodeTypes>), i32> {
-        let c_path = CString::new(path).unwrap();
+    /// Skip the `.` and `..` entries while iterating.
+    pub fn set_skip_dots(&mut self, skip: bool) {
+        self.skip_dots = skip;
+    }
+
+    /// Restarts the iteration at the first entry.
+    pub fn rewind(&mut self) {
+        unsafe { ext4_dir_entry_rewind(&mut self.dir) };
+        self.done = false;
+    }
+
+    #[deprecated(note = "iterate over an `Ext4Dir` opened at the path instead")]
+    pub fn lwext4_dir_entries(&self, path: &str) -> Ext4Result<(Vec<Vec<u8>>, Vec<InodeTypes>)> {
+        let c_path = resolve_path(path)?;
         let mut d: ext4_dir = unsafe { core::mem::zeroed() };
 
         let mut name: Vec<Vec<u8>> = Vec::new();
@@ -109,3 +149,37 @@ impl Ext4Dir {
         Ok((name, inode_type))
     }
 }
+
+impl Iterator for Ext4Dir {
+    type Item = Ext4Result<DirEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            let de = unsafe { ext4_dir_entry_next(&mut self.dir) };
+            if de.is_null() {
+                self.done = true;
+                // lwext4 also returns no entry if reading the directory
+                // fails, but then leaves the offset short of the end.
+                if self.dir.next_off != DIR_ENTRY_OFFSET_TERM {
+                    error!("ext4_dir_entry_next: failed at offset {}", self.dir.next_off);
+                    return Some(Err(Ext4Error::from_kind(
+                        Ext4ErrorKind::Io,
+                        "ext4_dir_entry_next",
+                    )));
+                }
+                return None;
+            }
+            let de = unsafe { &*de };
+            let entry = DirEntry {
+                inode: de.inode,
+                name: de.name[..de.name_length as usize].to_vec(),
+                file_type: InodeTypes::from_dir_entry_type(de.inode_type),
+            };
+            if self.skip_dots && entry.is_dot() {
+                continue;
+            }
+            return Some(Ok(entry));
+        }
+        None
+    }
+}
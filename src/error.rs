@@ -0,0 +1,234 @@
+use alloc::{ffi::CString, string::String};
+use core::fmt;
+
+use crate::bindings::{
+    E2BIG, EACCES, EEXIST, EFAULT, EFBIG, EINVAL, EIO, EISDIR, EMLINK, ENODATA, ENODEV, ENOENT,
+    ENOMEM, ENOSPC, ENOTDIR, ENOTEMPTY, ENOTSUP, ENXIO, EPERM, ERANGE, EROFS,
+};
+
+/// POSIX errno values that lwext4 does not define itself.
+const EBUSY: u32 = 16;
+const EXDEV: u32 = 18;
+const ENAMETOOLONG: u32 = 36;
+const EUCLEAN: u32 = 117;
+
+/// Result type of the public lwext4 API.
+pub type Ext4Result<T> = Result<T, Ext4Error>;
+
+/// Category of an [`Ext4Error`], derived from the errno returned by lwext4.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ext4ErrorKind {
+    /// No such file or directory (`ENOENT`).
+    NotFound,
+    /// The entry already exists (`EEXIST`).
+    Exists,
+    /// No space left on the volume (`ENOSPC`).
+    NoSpace,
+    /// The volume is mounted read-only (`EROFS`).
+    ReadOnly,
+    /// A path component is not a directory (`ENOTDIR`).
+    NotDir,
+    /// The target is a directory (`EISDIR`).
+    IsDir,
+    /// The directory is not empty (`ENOTEMPTY`).
+    NotEmpty,
+    /// Block device I/O failure (`EIO`).
+    Io,
+    /// On-disk structures are inconsistent (`EUCLEAN`).
+    Corrupted,
+    /// The operation or an on-disk feature is not supported (`ENOTSUP`).
+    Unsupported,
+    /// The path can not be passed to lwext4, e.g. it contains a NUL byte or
+    /// is too long (`ENAMETOOLONG`).
+    InvalidPath,
+    /// An argument is invalid (`EINVAL`).
+    InvalidInput,
+    /// Operation not permitted (`EPERM`, `EACCES`).
+    PermissionDenied,
+    /// The device or mount point is busy (`EBUSY`).
+    Busy,
+    /// No such device or mount point (`ENODEV`, `ENXIO`).
+    NoDevice,
+    /// Source and target are on different volumes (`EXDEV`).
+    CrossDevice,
+    /// Out of memory (`ENOMEM`).
+    NoMemory,
+    /// The file would grow beyond the maximum size (`EFBIG`).
+    FileTooLarge,
+    /// Too many hard links (`EMLINK`).
+    TooManyLinks,
+    /// The requested attribute does not exist (`ENODATA`).
+    NoData,
+    /// A result does not fit into the given buffer (`ERANGE`, `E2BIG`).
+    OutOfRange,
+    /// Any other errno.
+    Other(i32),
+}
+
+impl Ext4ErrorKind {
+    /// Maps a (positive) errno returned by lwext4 to an error kind.
+    pub fn from_errno(errno: i32) -> Self {
+        match errno.unsigned_abs() {
+            ENOENT => Self::NotFound,
+            EEXIST => Self::Exists,
+            ENOSPC => Self::NoSpace,
+            EROFS => Self::ReadOnly,
+            ENOTDIR => Self::NotDir,
+            EISDIR => Self::IsDir,
+            ENOTEMPTY => Self::NotEmpty,
+            EIO | EFAULT => Self::Io,
+            EUCLEAN => Self::Corrupted,
+            ENOTSUP => Self::Unsupported,
+            ENAMETOOLONG => Self::InvalidPath,
+            EINVAL => Self::InvalidInput,
+            EPERM | EACCES => Self::PermissionDenied,
+            EBUSY => Self::Busy,
+            ENODEV | ENXIO => Self::NoDevice,
+            EXDEV => Self::CrossDevice,
+            ENOMEM => Self::NoMemory,
+            EFBIG => Self::FileTooLarge,
+            EMLINK => Self::TooManyLinks,
+            ENODATA => Self::NoData,
+            ERANGE | E2BIG => Self::OutOfRange,
+            _ => Self::Other(errno),
+        }
+    }
+
+    /// Returns the POSIX errno corresponding to this kind.
+    pub fn errno(&self) -> i32 {
+        let e = match self {
+            Self::NotFound => ENOENT,
+            Self::Exists => EEXIST,
+            Self::NoSpace => ENOSPC,
+            Self::ReadOnly => EROFS,
+            Self::NotDir => ENOTDIR,
+            Self::IsDir => EISDIR,
+            Self::NotEmpty => ENOTEMPTY,
+            Self::Io => EIO,
+            Self::Corrupted => EUCLEAN,
+            Self::Unsupported => ENOTSUP,
+            Self::InvalidPath => ENAMETOOLONG,
+            Self::InvalidInput => EINVAL,
+            Self::PermissionDenied => EPERM,
+            Self::Busy => EBUSY,
+            Self::NoDevice => ENODEV,
+            Self::CrossDevice => EXDEV,
+            Self::NoMemory => ENOMEM,
+            Self::FileTooLarge => EFBIG,
+            Self::TooManyLinks => EMLINK,
+            Self::NoData => ENODATA,
+            Self::OutOfRange => ERANGE,
+            Self::Other(e) => return *e,
+        };
+        e as i32
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::NotFound => "no such file or directory",
+            Self::Exists => "file exists",
+            Self::NoSpace => "no space left on device",
+            Self::ReadOnly => "read-only file system",
+            Self::NotDir => "not a directory",
+            Self::IsDir => "is a directory",
+            Self::NotEmpty => "directory not empty",
+            Self::Io => "I/O error",
+            Self::Corrupted => "file system corrupted",
+            Self::Unsupported => "operation not supported",
+            Self::InvalidPath => "invalid path",
+            Self::InvalidInput => "invalid argument",
+            Self::PermissionDenied => "operation not permitted",
+            Self::Busy => "device or resource busy",
+            Self::NoDevice => "no such device",
+            Self::CrossDevice => "cross-device link",
+            Self::NoMemory => "out of memory",
+            Self::FileTooLarge => "file too large",
+            Self::TooManyLinks => "too many links",
+            Self::NoData => "no data available",
+            Self::OutOfRange => "result out of range",
+            Self::Other(_) => "unknown error",
+        }
+    }
+}
+
+impl fmt::Display for Ext4ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Other(e) => write!(f, "{} (errno {})", self.as_str(), e),
+            _ => f.write_str(self.as_str()),
+        }
+    }
+}
+
+/// Error returned by the lwext4 API.
+///
+/// Carries the [`Ext4ErrorKind`], the lwext4 call that failed and, if the
+/// operation was path based, the path it was called with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ext4Error {
+    kind: Ext4ErrorKind,
+    op: &'static str,
+    path: Option<String>,
+}
+
+impl Ext4Error {
+    /// Creates an error from an errno returned by the lwext4 call `op`.
+    pub fn new(errno: i32, op: &'static str) -> Self {
+        Self::from_kind(Ext4ErrorKind::from_errno(errno), op)
+    }
+
+    pub fn from_kind(kind: Ext4ErrorKind, op: &'static str) -> Self {
+        Self {
+            kind,
+            op,
+            path: None,
+        }
+    }
+
+    /// Attaches the path the failing operation was called with.
+    pub fn with_path(mut self, path: &str) -> Self {
+        self.path = Some(String::from(path));
+        self
+    }
+
+    pub fn kind(&self) -> Ext4ErrorKind {
+        self.kind
+    }
+
+    /// The POSIX errno of this error.
+    pub fn errno(&self) -> i32 {
+        self.kind.errno()
+    }
+
+    /// Name of the operation that failed, e.g. `"ext4_fopen2"`.
+    pub fn op(&self) -> &'static str {
+        self.op
+    }
+
+    pub fn path(&self) -> Option<&str> {
+        self.path.as_deref()
+    }
+}
+
+impl fmt::Display for Ext4Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.op, self.kind)?;
+        if let Some(path) = &self.path {
+            write!(f, ", path = {}", path)?;
+        }
+        Ok(())
+    }
+}
+
+impl From<Ext4Error> for i32 {
+    fn from(e: Ext4Error) -> i32 {
+        e.errno()
+    }
+}
+
+/// Converts `path` into a C string, rejecting interior NUL bytes.
+pub(crate) fn to_cstring(path: &str) -> Ext4Result<CString> {
+    CString::new(path).map_err(|_| {
+        Ext4Error::from_kind(Ext4ErrorKind::InvalidPath, "CString::new").with_path(path)
+    })
+}
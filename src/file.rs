@@ -1,7 +1,9 @@
-use alloc::{ffi::CString, vec::Vec};
-use core::{convert::TryInto, mem::MaybeUninit};
+use core::{convert::TryInto, ffi::CStr, mem::MaybeUninit};
 
 use crate::bindings::*;
+use crate::error::{Ext4Error, Ext4Result};
+use crate::metadata::{self, Metadata, SetTime};
+use crate::mount::{device_flush, resolve_path};
 
 pub struct Ext4File(ext4_file);
 
@@ -14,20 +16,20 @@ impl Drop for Ext4File {
 }
 
 impl Ext4File {
-    pub fn open(path: &str, flags: i32) -> Result<Self, i32> {
-        let c_path = CString::new(path).expect("CString::new failed");
+    pub fn open(path: &str, flags: i32) -> Ext4Result<Self> {
+        let c_path = resolve_path(path)?;
         let mut file = MaybeUninit::uninit();
         let r = unsafe { ext4_fopen2(file.as_mut_ptr(), c_path.as_ptr(), flags) };
         match r {
             0 => unsafe { Ok(Self(file.assume_init())) },
             e => {
                 error!("ext4_fopen: {}, rc = {}", path, r);
-                Err(e)
+                Err(Ext4Error::new(e, "ext4_fopen2").with_path(path))
             }
         }
     }
 
-    pub fn seek(&mut self, offset: i64, seek_type: u32) -> Result<(), i32> {
+    pub fn seek(&mut self, offset: i64, seek_type: u32) -> Ext4Result<()> {
         let mut offset = offset;
         let size = self.size() as i64;
 
@@ -40,7 +42,7 @@ impl Ext4File {
             0 => Ok(()),
             _ => {
                 error!("ext4_fseek error: rc = {}", r);
-                Err(r)
+                Err(Ext4Error::new(r, "ext4_fseek"))
             }
         }
     }
@@ -50,7 +52,7 @@ impl Ext4File {
         r
     }
 
-    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, i32> {
+    pub fn read(&mut self, buf: &mut [u8]) -> Ext4Result<usize> {
         let mut r_cnt = 0;
         let r = unsafe { ext4_fread(&mut self.0, buf.as_mut_ptr() as _, buf.len(), &mut r_cnt) };
 
@@ -58,12 +60,12 @@ impl Ext4File {
             0 => Ok(r_cnt),
             e => {
                 error!("ext4_fread: rc = {}", r);
-                Err(e)
+                Err(Ext4Error::new(e, "ext4_fread"))
             }
         }
     }
 
-    pub fn write(&mut self, buf: &[u8]) -> Result<usize, i32> {
+    pub fn write(&mut self, buf: &[u8]) -> Ext4Result<usize> {
         let mut w_cnt = 0;
         let r = unsafe { ext4_fwrite(&mut self.0, buf.as_ptr() as _, buf.len(), &mut w_cnt) };
 
@@ -71,27 +73,77 @@ impl Ext4File {
             0 => Ok(w_cnt),
             e => {
                 error!("ext4_fwrite: rc = {}", r);
-                Err(e)
+                Err(Ext4Error::new(e, "ext4_fwrite"))
             }
         }
     }
 
-    pub fn truncate(&mut self, size: u64) -> Result<(), i32> {
+    pub fn truncate(&mut self, size: u64) -> Ext4Result<()> {
         let r = unsafe { ext4_ftruncate(&mut self.0, size) };
         match r {
             0 => Ok(()),
             e => {
                 error!("ext4_ftruncate: rc = {}", r);
-                Err(e)
+                Err(Ext4Error::new(e, "ext4_ftruncate"))
             }
         }
     }
 
+    /// Makes the data and metadata of the file durable, like `fsync`.
+    ///
+    /// lwext4 keeps no per-file dirty state, so this flushes the whole block
+    /// cache of the volume with `ext4_cache_flush` and then drains the device
+    /// write cache with `KernelDevOp::flush`, in that order.
+    pub fn sync_all(&mut self) -> Ext4Result<()> {
+        let mp = unsafe { &*self.0.mp };
+        let r = unsafe { ext4_cache_flush(mp.name.as_ptr()) };
+        if r != EOK as i32 {
+            error!("ext4_cache_flush: rc = {}", r);
+            return Err(Ext4Error::new(r, "ext4_cache_flush"));
+        }
+        let name = unsafe { CStr::from_ptr(mp.name.as_ptr()) };
+        if let Some(flush) = name.to_str().ok().and_then(device_flush) {
+            let r = unsafe { flush(mp.fs.bdev) };
+            if r != EOK as i32 {
+                return Err(Ext4Error::new(r, "ext4_blockdev_flush"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Like `fdatasync`. Metadata shares the block cache with the data, so
+    /// this is the same as [`Ext4File::sync_all`].
+    pub fn sync_data(&mut self) -> Ext4Result<()> {
+        self.sync_all()
+    }
+
     pub fn size(&mut self) -> u64 {
         unsafe { ext4_fsize(&mut self.0) }
     }
 
-    pub fn file_get_blk_idx(&mut self) -> Result<u64, i32> {
+    /// Reads the metadata of the open file, like `fstat`.
+    pub fn metadata(&self) -> Ext4Result<Metadata> {
+        Metadata::from_file(&self.0)
+    }
+
+    /// Sets the permission bits of the open file, like `fchmod`.
+    pub fn set_permissions(&mut self, mode: u32) -> Ext4Result<()> {
+        metadata::set_permissions(self.0.mp, self.0.inode, mode)
+    }
+
+    /// Changes the owner and group of the open file, like `fchown`.
+    pub fn set_owner(&mut self, uid: Option<u32>, gid: Option<u32>) -> Ext4Result<()> {
+        metadata::set_owner(self.0.mp, self.0.inode, uid, gid)
+    }
+
+    /// Changes the access and modification times of the open file, like
+    /// `futimens`. [`SetTime::Now`] needs a
+    /// [`MountOptions::clock`](crate::MountOptions::clock).
+    pub fn set_times(&mut self, atime: SetTime, mtime: SetTime) -> Ext4Result<()> {
+        metadata::set_times(self.0.mp, self.0.inode, atime, mtime)
+    }
+
+    pub fn file_get_blk_idx(&mut self) -> Ext4Result<u64> {
         let block_idx;
         unsafe {
             let mut inode_ref = ext4_inode_ref {
@@ -108,7 +160,7 @@ impl Ext4File {
             let r = ext4_fs_get_inode_ref(&mut (*self.0.mp).fs, self.0.inode, &mut inode_ref);
             if r != EOK as i32 {
                 error!("ext4_fs_get_inode_ref: rc = {}", r);
-                return Err(r);
+                return Err(Ext4Error::new(r, "ext4_fs_get_inode_ref"));
             }
             let sb = (*self.0.mp).fs.sb;
             let block_size = 1024 << sb.log_block_size.to_le();
@@ -117,7 +169,7 @@ impl Ext4File {
             let r = ext4_fs_get_inode_dblk_idx(&mut inode_ref, iblock_idx, &mut fblock, true);
             if r != EOK as i32 {
                 error!("ext4_fs_get_inode_dblk_idx: rc = {}", r);
-                return Err(r);
+                return Err(Ext4Error::new(r, "ext4_fs_get_inode_dblk_idx"));
             }
             ext4_fs_put_inode_ref(&mut inode_ref);
 
@@ -162,6 +214,23 @@ pub enum InodeTypes {
     EXT4_INODE_MODE_TYPE_MASK = 0xF000,
 }
 
+impl InodeTypes {
+    /// Maps an `EXT4_DE_*` directory entry type to the `EXT4_INODE_MODE_*`
+    /// type reported for the inode itself. `EXT4_DE_UNKNOWN` is kept.
+    pub fn from_dir_entry_type(de_type: u8) -> Self {
+        match de_type as u32 {
+            EXT4_DE_REG_FILE => InodeTypes::EXT4_INODE_MODE_FILE,
+            EXT4_DE_DIR => InodeTypes::EXT4_INODE_MODE_DIRECTORY,
+            EXT4_DE_CHRDEV => InodeTypes::EXT4_INODE_MODE_CHARDEV,
+            EXT4_DE_BLKDEV => InodeTypes::EXT4_INODE_MODE_BLOCKDEV,
+            EXT4_DE_FIFO => InodeTypes::EXT4_INODE_MODE_FIFO,
+            EXT4_DE_SOCK => InodeTypes::EXT4_INODE_MODE_SOCKET,
+            EXT4_DE_SYMLINK => InodeTypes::EXT4_INODE_MODE_SOFTLINK,
+            _ => InodeTypes::EXT4_DE_UNKNOWN,
+        }
+    }
+}
+
 impl From<usize> for InodeTypes {
     fn from(num: usize) -> InodeTypes {
         match num {
@@ -0,0 +1,123 @@
+//! A block device backed by a host file, e.g. an `.img` image.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::bindings::EIO;
+use crate::blockdev::BlockDevice;
+
+/// Default sector size of a [`FileDisk`].
+const FILE_DISK_BSIZE: u32 = 512;
+
+/// A disk image in a host file, usable wherever a [`KernelDevOp`] device is
+/// expected.
+///
+/// ```ignore
+/// let disk = FileDisk::open("rootfs.img")?;
+/// let fs = Ext4Fs::<FileDisk>::new(disk, MountOptions::new())?;
+/// ```
+///
+/// [`KernelDevOp`]: crate::KernelDevOp
+#[derive(Debug)]
+pub struct FileDisk {
+    file: File,
+    block_size: u32,
+    num_blocks: u64,
+}
+
+impl FileDisk {
+    /// Opens an existing image for reading and writing.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        Self::from_file(file)
+    }
+
+    /// Opens an existing image read-only. Writes to it fail, so mount it
+    /// with [`MountOptions::read_only`](crate::MountOptions::read_only).
+    pub fn open_read_only<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::from_file(File::open(path)?)
+    }
+
+    /// Creates an image of `size` bytes, truncating an existing one.
+    pub fn create<P: AsRef<Path>>(path: P, size: u64) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        file.set_len(size)?;
+        Self::from_file(file)
+    }
+
+    /// Wraps an open file. A trailing partial sector is not used.
+    pub fn from_file(file: File) -> io::Result<Self> {
+        let len = file.metadata()?.len();
+        Ok(Self {
+            file,
+            block_size: FILE_DISK_BSIZE,
+            num_blocks: len / FILE_DISK_BSIZE as u64,
+        })
+    }
+
+    /// Uses sectors of `block_size` bytes instead of 512, e.g. 4096 to
+    /// emulate a 4Kn disk.
+    pub fn with_block_size(mut self, block_size: u32) -> Self {
+        let len = self.num_blocks * self.block_size as u64;
+        self.block_size = block_size;
+        self.num_blocks = len / block_size as u64;
+        self
+    }
+
+    pub fn into_inner(self) -> File {
+        self.file
+    }
+
+    fn seek_to(&mut self, lba: u64) -> io::Result<()> {
+        self.file
+            .seek(SeekFrom::Start(lba * self.block_size as u64))
+            .map(|_| ())
+    }
+
+    fn check_range(&self, lba: u64, count: u32, len: usize) -> Result<(), i32> {
+        if len != count as usize * self.block_size as usize || lba + count as u64 > self.num_blocks
+        {
+            error!("FileDisk: bad request lba {} count {} len {}", lba, count, len);
+            return Err(EIO as i32);
+        }
+        Ok(())
+    }
+}
+
+fn errno(e: io::Error) -> i32 {
+    e.raw_os_error().unwrap_or(EIO as i32)
+}
+
+impl BlockDevice for FileDisk {
+    fn read_blocks(&mut self, lba: u64, count: u32, buf: &mut [u8]) -> Result<(), i32> {
+        self.check_range(lba, count, buf.len())?;
+        self.seek_to(lba)
+            .and_then(|_| self.file.read_exact(buf))
+            .map_err(errno)
+    }
+
+    fn write_blocks(&mut self, lba: u64, count: u32, buf: &[u8]) -> Result<(), i32> {
+        self.check_range(lba, count, buf.len())?;
+        self.seek_to(lba)
+            .and_then(|_| self.file.write_all(buf))
+            .map_err(errno)
+    }
+
+    fn flush(&mut self) -> Result<(), i32> {
+        self.file.sync_data().map_err(errno)
+    }
+
+    fn num_blocks(&mut self) -> u64 {
+        self.num_blocks
+    }
+
+    fn block_size(&mut self) -> u32 {
+        self.block_size
+    }
+}
@@ -0,0 +1,316 @@
+use alloc::string::String;
+#[cfg(feature = "xattr")]
+use alloc::vec::Vec;
+use core::{
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+};
+
+use crate::blockdev::{Ext4BlockWrapper, KernelDevOp, MountOptions};
+use crate::dir::{DirEntry, Ext4Dir};
+use crate::error::Ext4Result;
+use crate::file::{Ext4File, InodeTypes};
+use crate::metadata::{Metadata, SetTime};
+#[cfg(feature = "xattr")]
+use crate::xattr::{self, XattrFlags};
+
+/// A mounted ext4 volume.
+///
+/// All paths taken by its methods are relative to the mount point. Files and
+/// directories opened through it borrow the filesystem, so they can not
+/// outlive it or be used after [`Ext4Fs::unmount`].
+pub struct Ext4Fs<K: KernelDevOp> {
+    inner: Ext4BlockWrapper<K>,
+}
+
+/// An open file borrowing the [`Ext4Fs`] it was opened from.
+pub struct FileHandle<'a> {
+    file: Ext4File,
+    _fs: PhantomData<&'a ()>,
+}
+
+/// An open directory borrowing the [`Ext4Fs`] it was opened from.
+pub struct DirHandle<'a> {
+    dir: Ext4Dir,
+    _fs: PhantomData<&'a ()>,
+}
+
+impl<K: KernelDevOp> Ext4Fs<K> {
+    /// Mounts `block_dev` with the given options.
+    pub fn new(block_dev: K::DevType, options: MountOptions) -> Ext4Result<Self> {
+        Ok(Self {
+            inner: Ext4BlockWrapper::new_with_options(block_dev, options)?,
+        })
+    }
+
+    /// Wraps an already mounted volume, e.g. one from
+    /// [`Ext4BlockWrapper::new_partition`].
+    pub fn from_wrapper(inner: Ext4BlockWrapper<K>) -> Self {
+        Self { inner }
+    }
+
+    pub fn mount_point(&self) -> &str {
+        self.inner.mount_point()
+    }
+
+    /// Unmounts the volume. Dropping the handle does the same but ignores
+    /// errors.
+    pub fn unmount(mut self) -> Ext4Result<()> {
+        self.inner.lwext4_umount().map(|_| ())
+    }
+
+    /// Unmounts the volume and returns its device, see
+    /// [`Ext4BlockWrapper::into_device`].
+    pub fn into_device(self) -> Ext4Result<K::DevType> {
+        self.inner.into_device()
+    }
+
+    /// Flushes the volume to the device, see [`Ext4BlockWrapper::sync`].
+    pub fn sync(&self) -> Ext4Result<()> {
+        self.inner.sync()
+    }
+
+    /// Replays the journal, see [`Ext4BlockWrapper::recover`].
+    pub fn recover(&mut self) -> Ext4Result<()> {
+        self.inner.recover()
+    }
+
+    /// Starts journaling until [`Ext4Fs::journal_stop`] or unmount.
+    pub fn journal_start(&mut self) -> Ext4Result<()> {
+        self.inner.journal_start()
+    }
+
+    pub fn journal_stop(&mut self) -> Ext4Result<()> {
+        self.inner.journal_stop()
+    }
+
+    /// Starts a journaled section that lasts as long as the returned guard.
+    ///
+    /// lwext4 commits every operation as its own journal transaction, so the
+    /// guard does not make several operations atomic; it makes sure the
+    /// journal is running while it is alive and, if it started it, stops it
+    /// again, which checkpoints the committed transactions to the volume.
+    ///
+    /// ```ignore
+    /// let tx = fs.transaction()?;
+    /// tx.create_dir("etc")?;
+    /// tx.rename("etc.new/passwd", "etc/passwd")?;
+    /// tx.commit()?;
+    /// ```
+    pub fn transaction(&mut self) -> Ext4Result<Transaction<'_, K>> {
+        let started = !self.inner.is_journal_started();
+        self.inner.journal_start()?;
+        Ok(Transaction { fs: self, started })
+    }
+
+    /// Turns a mount-relative path into the absolute one lwext4 is called with.
+    fn abs_path(&self, path: &str) -> String {
+        let mut abs = String::from(self.mount_point());
+        abs.push_str(path.trim_start_matches('/'));
+        abs
+    }
+
+    /// Opens a file with `O_*` flags, e.g. `O_RDWR | O_CREAT`.
+    pub fn open(&self, path: &str, flags: i32) -> Ext4Result<FileHandle<'_>> {
+        Ok(FileHandle {
+            file: Ext4File::open(&self.abs_path(path), flags)?,
+            _fs: PhantomData,
+        })
+    }
+
+    pub fn open_dir(&self, path: &str) -> Ext4Result<DirHandle<'_>> {
+        Ok(DirHandle {
+            dir: Ext4Dir::open(&self.abs_path(path))?,
+            _fs: PhantomData,
+        })
+    }
+
+    pub fn create_dir(&self, path: &str) -> Ext4Result<DirHandle<'_>> {
+        Ok(DirHandle {
+            dir: Ext4Dir::create(&self.abs_path(path))?,
+            _fs: PhantomData,
+        })
+    }
+
+    pub fn exists(&self, path: &str, types: InodeTypes) -> bool {
+        crate::lwext4_check_inode_exist(&self.abs_path(path), types)
+    }
+
+    /// Removes a file, or a directory together with its contents.
+    pub fn remove(&self, path: &str) -> Ext4Result<()> {
+        let path = self.abs_path(path);
+        if crate::lwext4_check_inode_exist(&path, InodeTypes::EXT4_DE_DIR) {
+            crate::lwext4_rmdir(&path)
+        } else {
+            crate::lwext4_rmfile(&path)
+        }
+    }
+
+    /// Renames a file or directory.
+    pub fn rename(&self, path: &str, new_path: &str) -> Ext4Result<()> {
+        let path = self.abs_path(path);
+        let new_path = self.abs_path(new_path);
+        if crate::lwext4_check_inode_exist(&path, InodeTypes::EXT4_DE_DIR) {
+            crate::lwext4_mvdir(&path, &new_path)
+        } else {
+            crate::lwext4_mvfile(&path, &new_path)
+        }
+    }
+
+    /// Creates a hard link `hardlink_path` to `path`.
+    pub fn link(&self, path: &str, hardlink_path: &str) -> Ext4Result<()> {
+        crate::lwext4_link(&self.abs_path(path), &self.abs_path(hardlink_path))
+    }
+
+    /// Creates a symbolic link at `path` pointing to `target`. The target is
+    /// stored as is.
+    pub fn symlink(&self, target: &str, path: &str) -> Ext4Result<()> {
+        crate::lwext4_symlink(target, &self.abs_path(path))
+    }
+
+    /// Reads the target of a symbolic link into `buf`, returning its length.
+    pub fn read_link(&self, path: &str, buf: &mut [u8]) -> Ext4Result<usize> {
+        crate::lwext4_readlink(&self.abs_path(path), buf)
+    }
+
+    pub fn metadata(&self, path: &str) -> Ext4Result<Metadata> {
+        Metadata::from_path(&self.abs_path(path))
+    }
+
+    /// Sets the permission bits of `path`, like `chmod`.
+    pub fn set_permissions(&self, path: &str, mode: u32) -> Ext4Result<()> {
+        crate::lwext4_chmod(&self.abs_path(path), mode)
+    }
+
+    /// Changes the owner and group of `path`, like `chown`.
+    pub fn set_owner(&self, path: &str, uid: Option<u32>, gid: Option<u32>) -> Ext4Result<()> {
+        crate::lwext4_chown(&self.abs_path(path), uid, gid)
+    }
+
+    /// Changes the access and modification times of `path`, like `utimensat`.
+    /// [`SetTime::Now`] needs a [`MountOptions::clock`].
+    pub fn set_times(&self, path: &str, atime: SetTime, mtime: SetTime) -> Ext4Result<()> {
+        crate::lwext4_utimens(&self.abs_path(path), atime, mtime)
+    }
+
+    /// Creates a device node, FIFO or socket, like `mknod`. See
+    /// [`crate::lwext4_mknod`].
+    pub fn mknod(
+        &self,
+        path: &str,
+        kind: InodeTypes,
+        mode: u32,
+        major: u32,
+        minor: u32,
+    ) -> Ext4Result<()> {
+        crate::lwext4_mknod(&self.abs_path(path), kind, mode, major, minor)
+    }
+
+    /// Reads the extended attribute `name`, e.g. `user.comment`.
+    #[cfg(feature = "xattr")]
+    pub fn get_xattr(&self, path: &str, name: &str) -> Ext4Result<Vec<u8>> {
+        xattr::get_xattr(&self.abs_path(path), name)
+    }
+
+    /// Sets the extended attribute `name` to `value`. The `flags` check is
+    /// not atomic with the update, see [`XattrFlags`].
+    #[cfg(feature = "xattr")]
+    pub fn set_xattr(
+        &self,
+        path: &str,
+        name: &str,
+        value: &[u8],
+        flags: XattrFlags,
+    ) -> Ext4Result<()> {
+        xattr::set_xattr(&self.abs_path(path), name, value, flags)
+    }
+
+    /// Lists the extended attribute names of `path`.
+    #[cfg(feature = "xattr")]
+    pub fn list_xattrs(&self, path: &str) -> Ext4Result<Vec<String>> {
+        xattr::list_xattrs(&self.abs_path(path))
+    }
+
+    /// Removes the extended attribute `name`.
+    #[cfg(feature = "xattr")]
+    pub fn remove_xattr(&self, path: &str, name: &str) -> Ext4Result<()> {
+        xattr::remove_xattr(&self.abs_path(path), name)
+    }
+}
+
+/// A journaled section of an [`Ext4Fs`], see [`Ext4Fs::transaction`].
+///
+/// Dereferences to the filesystem. Dropping it without
+/// [`Transaction::commit`] does the same but ignores errors.
+pub struct Transaction<'a, K: KernelDevOp> {
+    fs: &'a mut Ext4Fs<K>,
+    /// Whether the journal was started by this guard.
+    started: bool,
+}
+
+impl<K: KernelDevOp> Transaction<'_, K> {
+    /// Ends the section, stopping the journal if it was started for it.
+    pub fn commit(mut self) -> Ext4Result<()> {
+        self.finish()
+    }
+
+    fn finish(&mut self) -> Ext4Result<()> {
+        if !self.started {
+            return Ok(());
+        }
+        self.started = false;
+        self.fs.journal_stop()
+    }
+}
+
+impl<K: KernelDevOp> Deref for Transaction<'_, K> {
+    type Target = Ext4Fs<K>;
+
+    fn deref(&self) -> &Ext4Fs<K> {
+        self.fs
+    }
+}
+
+impl<K: KernelDevOp> Drop for Transaction<'_, K> {
+    fn drop(&mut self) {
+        if let Err(e) = self.finish() {
+            error!("Failed to end the transaction: {}", e);
+        }
+    }
+}
+
+impl Deref for FileHandle<'_> {
+    type Target = Ext4File;
+
+    fn deref(&self) -> &Ext4File {
+        &self.file
+    }
+}
+
+impl DerefMut for FileHandle<'_> {
+    fn deref_mut(&mut self) -> &mut Ext4File {
+        &mut self.file
+    }
+}
+
+impl Deref for DirHandle<'_> {
+    type Target = Ext4Dir;
+
+    fn deref(&self) -> &Ext4Dir {
+        &self.dir
+    }
+}
+
+impl DerefMut for DirHandle<'_> {
+    fn deref_mut(&mut self) -> &mut Ext4Dir {
+        &mut self.dir
+    }
+}
+
+impl Iterator for DirHandle<'_> {
+    type Item = Ext4Result<DirEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.dir.next()
+    }
+}
@@ -0,0 +1,278 @@
+use alloc::{string::String, vec, vec::Vec};
+use core::{convert::TryInto, fmt};
+
+use crate::blockdev::{Ext4BlockWrapper, KernelDevOp};
+use crate::error::{Ext4Error, Ext4ErrorKind, Ext4Result};
+
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+const GPT_HEADER_MIN_SIZE: usize = 92;
+const GPT_ENTRY_MIN_SIZE: usize = 128;
+/// Upper bound of the partition entry array we are willing to read.
+const GPT_ENTRIES_MAX_BYTES: usize = 1 << 20;
+
+/// A GUID as stored on disk (first three fields little endian).
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Guid(pub [u8; 16]);
+
+impl Guid {
+    /// Partition type of Linux filesystem data, `0FC63DAF-8483-4772-8E79-3D69D8477DE4`.
+    pub const LINUX_FILESYSTEM: Guid = Guid([
+        0xaf, 0x3d, 0xc6, 0x0f, 0x83, 0x84, 0x72, 0x47, 0x8e, 0x79, 0x3d, 0x69, 0xd8, 0x47, 0x7d,
+        0xe4,
+    ]);
+    /// Type of an unused partition entry.
+    pub const UNUSED: Guid = Guid([0; 16]);
+
+    pub fn is_unused(&self) -> bool {
+        *self == Self::UNUSED
+    }
+}
+
+impl fmt::Display for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let b = &self.0;
+        write!(
+            f,
+            "{:02X}{:02X}{:02X}{:02X}-{:02X}{:02X}-{:02X}{:02X}-{:02X}{:02X}-",
+            b[3], b[2], b[1], b[0], b[5], b[4], b[7], b[6], b[8], b[9]
+        )?;
+        for x in &b[10..] {
+            write!(f, "{:02X}", x)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// A used entry of the GPT partition entry array.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GptPartition {
+    /// Index in the partition entry array.
+    pub index: usize,
+    pub type_guid: Guid,
+    pub unique_guid: Guid,
+    pub name: String,
+    pub first_lba: u64,
+    /// Last LBA, inclusive.
+    pub last_lba: u64,
+    pub attributes: u64,
+    /// Start of the partition in bytes, for `ext4_blockdev::part_offset`.
+    pub offset: u64,
+    /// Size of the partition in bytes, for `ext4_blockdev::part_size`.
+    pub size: u64,
+}
+
+/// A validated GUID partition table.
+///
+/// A partition can be mounted with
+/// [`Ext4BlockWrapper::new_partition`](crate::Ext4BlockWrapper::new_partition):
+///
+/// ```ignore
+/// let gpt = Gpt::read::<Disk>(&mut disk)?;
+/// let p = gpt.find_by_type(&Guid::LINUX_FILESYSTEM).next().unwrap();
+/// let fs = Ext4BlockWrapper::<Disk>::new_partition(disk, p.offset, p.size, options)?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct Gpt {
+    pub disk_guid: Guid,
+    partitions: Vec<GptPartition>,
+}
+
+impl Gpt {
+    /// Reads the GPT of `block_dev`, falling back to the backup header at the
+    /// end of the device if the primary one is damaged. LBAs are in units of
+    /// the device's [`KernelDevOp::sector_size`].
+    pub fn read<K: KernelDevOp>(block_dev: &mut K::DevType) -> Ext4Result<Self> {
+        let lba_size = Ext4BlockWrapper::<K>::sector_size(block_dev)? as u64;
+        match Self::read_at::<K>(block_dev, 1, lba_size) {
+            Ok(gpt) => Ok(gpt),
+            Err(e) => {
+                warn!("gpt: primary header invalid ({}), trying the backup", e);
+                let dev_size = K::size(block_dev)
+                    .map_err(|_| Ext4Error::from_kind(Ext4ErrorKind::Io, "gpt_read"))?;
+                let last_lba = (dev_size / lba_size).saturating_sub(1);
+                Self::read_at::<K>(block_dev, last_lba, lba_size).map_err(|_| e)
+            }
+        }
+    }
+
+    fn read_at<K: KernelDevOp>(
+        block_dev: &mut K::DevType,
+        lba: u64,
+        lba_size: u64,
+    ) -> Ext4Result<Self> {
+        let mut hdr = read_lbas::<K>(block_dev, lba, lba_size as usize, lba_size)?;
+
+        if &hdr[0..8] != GPT_SIGNATURE {
+            return Err(Ext4Error::from_kind(Ext4ErrorKind::NotFound, "gpt_read"));
+        }
+        let header_size = le32(&hdr, 12) as usize;
+        if !(GPT_HEADER_MIN_SIZE..=hdr.len()).contains(&header_size) {
+            error!("gpt: invalid header size {}", header_size);
+            return Err(corrupted());
+        }
+        let header_crc = le32(&hdr, 16);
+        hdr[16..20].fill(0);
+        if crc32(&hdr[..header_size]) != header_crc {
+            error!("gpt: header CRC mismatch at LBA {}", lba);
+            return Err(corrupted());
+        }
+        if le64(&hdr, 24) != lba {
+            error!("gpt: header is not at its own LBA {}", lba);
+            return Err(corrupted());
+        }
+
+        let mut disk_guid = Guid::default();
+        disk_guid.0.copy_from_slice(&hdr[56..72]);
+        let entries_lba = le64(&hdr, 72);
+        let num_entries = le32(&hdr, 80) as usize;
+        let entry_size = le32(&hdr, 84) as usize;
+        let entries_crc = le32(&hdr, 88);
+        // The UEFI spec requires 128 * 2^n bytes per entry.
+        if entry_size < GPT_ENTRY_MIN_SIZE
+            || !entry_size.is_power_of_two()
+            || num_entries.saturating_mul(entry_size) > GPT_ENTRIES_MAX_BYTES
+        {
+            error!("gpt: invalid entry array {} x {}", num_entries, entry_size);
+            return Err(corrupted());
+        }
+
+        let entries = read_lbas::<K>(block_dev, entries_lba, num_entries * entry_size, lba_size)?;
+        if crc32(&entries) != entries_crc {
+            error!("gpt: partition entry array CRC mismatch");
+            return Err(corrupted());
+        }
+
+        let mut partitions = Vec::new();
+        for (index, entry) in entries.chunks_exact(entry_size).enumerate() {
+            let mut type_guid = Guid::default();
+            type_guid.0.copy_from_slice(&entry[0..16]);
+            if type_guid.is_unused() {
+                continue;
+            }
+            let mut unique_guid = Guid::default();
+            unique_guid.0.copy_from_slice(&entry[16..32]);
+            let first_lba = le64(entry, 32);
+            let last_lba = le64(entry, 40);
+            if last_lba < first_lba {
+                error!("gpt: partition {} ends before it starts", index);
+                return Err(corrupted());
+            }
+            let name_units = entry[56..128]
+                .chunks_exact(2)
+                .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                .take_while(|&c| c != 0);
+            let name = char::decode_utf16(name_units)
+                .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                .collect();
+
+            partitions.push(GptPartition {
+                index,
+                type_guid,
+                unique_guid,
+                name,
+                first_lba,
+                last_lba,
+                attributes: le64(entry, 48),
+                offset: first_lba * lba_size,
+                size: (last_lba - first_lba + 1) * lba_size,
+            });
+        }
+        debug!("gpt: {} partitions, disk {}", partitions.len(), disk_guid);
+        Ok(Self {
+            disk_guid,
+            partitions,
+        })
+    }
+
+    /// Used partitions, in entry array order.
+    pub fn partitions(&self) -> &[GptPartition] {
+        &self.partitions
+    }
+
+    /// Returns the partition at `index` of the entry array.
+    pub fn partition(&self, index: usize) -> Option<&GptPartition> {
+        self.partitions.iter().find(|p| p.index == index)
+    }
+
+    pub fn find_by_type<'a>(
+        &'a self,
+        type_guid: &'a Guid,
+    ) -> impl Iterator<Item = &'a GptPartition> + 'a {
+        self.partitions
+            .iter()
+            .filter(move |p| p.type_guid == *type_guid)
+    }
+
+    pub fn find_by_unique_guid(&self, guid: &Guid) -> Option<&GptPartition> {
+        self.partitions.iter().find(|p| p.unique_guid == *guid)
+    }
+
+    pub fn find_by_name(&self, name: &str) -> Option<&GptPartition> {
+        self.partitions.iter().find(|p| p.name == name)
+    }
+}
+
+fn corrupted() -> Ext4Error {
+    Ext4Error::from_kind(Ext4ErrorKind::Corrupted, "gpt_read")
+}
+
+/// Reads `len` bytes starting at sector `lba`, rounding the read up to whole
+/// sectors.
+fn read_lbas<K: KernelDevOp>(
+    block_dev: &mut K::DevType,
+    lba: u64,
+    len: usize,
+    lba_size: u64,
+) -> Ext4Result<Vec<u8>> {
+    let lba_size = lba_size as usize;
+    let mut buf = vec![0u8; len.div_ceil(lba_size) * lba_size];
+    K::read_blocks(block_dev, lba, &mut buf).map_err(|e| {
+        error!("gpt: failed to read LBA {}: {}", lba, e);
+        Ext4Error::from_kind(Ext4ErrorKind::Io, "gpt_read")
+    })?;
+    buf.truncate(len);
+    Ok(buf)
+}
+
+fn le32(buf: &[u8], off: usize) -> u32 {
+    u32::from_le_bytes(buf[off..off + 4].try_into().unwrap())
+}
+
+fn le64(buf: &[u8], off: usize) -> u64 {
+    u64::from_le_bytes(buf[off..off + 8].try_into().unwrap())
+}
+
+/// CRC-32 (IEEE 802.3) as used by the GPT header and entry array.
+pub fn crc32(data: &[u8]) -> u32 {
+    const TABLE: [u32; 256] = {
+        let mut table = [0u32; 256];
+        let mut i = 0;
+        while i < 256 {
+            let mut c = i as u32;
+            let mut k = 0;
+            while k < 8 {
+                c = if c & 1 != 0 {
+                    0xedb8_8320 ^ (c >> 1)
+                } else {
+                    c >> 1
+                };
+                k += 1;
+            }
+            table[i] = c;
+            i += 1;
+        }
+        table
+    };
+
+    let mut crc = !0u32;
+    for &b in data {
+        crc = TABLE[((crc ^ b as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
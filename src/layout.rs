@@ -0,0 +1,110 @@
+//! Compile-time checks that the bindings agree with the C layout of the
+//! lwext4 structures shared with Rust. The checked-in `src/bindings.rs` was
+//! generated for x86_64, so these catch a target whose C ABI lays them out
+//! differently; use the `bindgen` feature there.
+//!
+//! The expected values are what GCC gives for LP64, for ILP32 with 8-byte
+//! aligned `u64` (Arm EABI, RISC-V) and for i386, where `u64` is only 4-byte
+//! aligned inside structures.
+
+use core::mem::{align_of, offset_of, size_of};
+
+use crate::bindings::{
+    ext4_blockdev, ext4_blockdev_iface, ext4_dir, ext4_file, ext4_fs, ext4_inode, ext4_mbr_bdevs,
+    ext4_mkfs_info, ext4_sblock,
+};
+
+macro_rules! assert_layout {
+    ($ty:ty, $size:expr, $align:expr) => {
+        const _: () = assert!(
+            size_of::<$ty>() == $size && align_of::<$ty>() == $align,
+            concat!("bindings do not match the C layout of ", stringify!($ty))
+        );
+    };
+}
+
+macro_rules! assert_offset {
+    ($ty:ty, $field:ident, $offset:expr) => {
+        const _: () = assert!(
+            offset_of!($ty, $field) == $offset,
+            concat!(
+                "bindings do not match the C layout of ",
+                stringify!($ty),
+                "::",
+                stringify!($field)
+            )
+        );
+    };
+}
+
+#[cfg(target_pointer_width = "64")]
+mod expected {
+    pub const BLOCKDEV: (usize, usize) = (72, 8);
+    pub const BLOCKDEV_IFACE: (usize, usize) = (96, 8);
+    pub const FILE: (usize, usize) = (32, 8);
+    pub const DIR: (usize, usize) = (304, 8);
+    pub const FS: (usize, usize) = (1136, 8);
+    pub const FS_SB: usize = 16;
+    pub const FS_INODE_BLOCK_LIMITS: usize = 1040;
+    pub const FS_LAST_INODE_BG_ID: usize = 1104;
+    pub const FS_CURR_TRANS: usize = 1128;
+    pub const MKFS_INFO: (usize, usize) = (80, 8);
+    pub const MKFS_INFO_LABEL: usize = 72;
+    pub const MBR_BDEVS: (usize, usize) = (288, 8);
+}
+
+#[cfg(all(target_pointer_width = "32", not(target_arch = "x86")))]
+mod expected {
+    pub const BLOCKDEV: (usize, usize) = (56, 8);
+    pub const BLOCKDEV_IFACE: (usize, usize) = (64, 8);
+    pub const FILE: (usize, usize) = (32, 8);
+    pub const DIR: (usize, usize) = (304, 8);
+    pub const FS: (usize, usize) = (1112, 8);
+    pub const FS_SB: usize = 8;
+    pub const FS_INODE_BLOCK_LIMITS: usize = 1032;
+    pub const FS_LAST_INODE_BG_ID: usize = 1096;
+    pub const FS_CURR_TRANS: usize = 1108;
+    pub const MKFS_INFO: (usize, usize) = (72, 8);
+    pub const MKFS_INFO_LABEL: usize = 68;
+    pub const MBR_BDEVS: (usize, usize) = (224, 8);
+}
+
+#[cfg(all(target_pointer_width = "32", target_arch = "x86"))]
+mod expected {
+    pub const BLOCKDEV: (usize, usize) = (48, 4);
+    pub const BLOCKDEV_IFACE: (usize, usize) = (56, 4);
+    pub const FILE: (usize, usize) = (28, 4);
+    pub const DIR: (usize, usize) = (300, 4);
+    pub const FS: (usize, usize) = (1112, 4);
+    pub const FS_SB: usize = 8;
+    pub const FS_INODE_BLOCK_LIMITS: usize = 1032;
+    pub const FS_LAST_INODE_BG_ID: usize = 1096;
+    pub const FS_CURR_TRANS: usize = 1108;
+    pub const MKFS_INFO: (usize, usize) = (72, 4);
+    pub const MKFS_INFO_LABEL: usize = 68;
+    pub const MBR_BDEVS: (usize, usize) = (192, 4);
+}
+
+assert_layout!(ext4_blockdev, expected::BLOCKDEV.0, expected::BLOCKDEV.1);
+assert_layout!(
+    ext4_blockdev_iface,
+    expected::BLOCKDEV_IFACE.0,
+    expected::BLOCKDEV_IFACE.1
+);
+assert_layout!(ext4_file, expected::FILE.0, expected::FILE.1);
+assert_layout!(ext4_dir, expected::DIR.0, expected::DIR.1);
+assert_layout!(ext4_fs, expected::FS.0, expected::FS.1);
+assert_offset!(ext4_fs, sb, expected::FS_SB);
+assert_offset!(ext4_fs, inode_block_limits, expected::FS_INODE_BLOCK_LIMITS);
+assert_offset!(ext4_fs, last_inode_bg_id, expected::FS_LAST_INODE_BG_ID);
+assert_offset!(ext4_fs, curr_trans, expected::FS_CURR_TRANS);
+assert_layout!(ext4_mkfs_info, expected::MKFS_INFO.0, expected::MKFS_INFO.1);
+// Everything up to `label` is made of 32-bit and smaller fields after a
+// leading `u64`, so only `label` moves between ABIs.
+assert_offset!(ext4_mkfs_info, dsc_size, 48);
+assert_offset!(ext4_mkfs_info, journal, 66);
+assert_offset!(ext4_mkfs_info, label, expected::MKFS_INFO_LABEL);
+assert_layout!(ext4_mbr_bdevs, expected::MBR_BDEVS.0, expected::MBR_BDEVS.1);
+// Packed, the same everywhere.
+assert_layout!(ext4_inode, 156, 1);
+assert_layout!(ext4_sblock, 1024, 1);
@@ -1,33 +1,68 @@
 //! Adapted from `MinotaurOS`, with some modification.
 
 #![no_std]
-#![feature(linkage)]
-#![feature(c_variadic, c_size_t)]
+#![cfg_attr(not(feature = "std"), feature(linkage))]
+#![cfg_attr(not(feature = "std"), feature(c_variadic, c_size_t))]
 #![feature(associated_type_defaults)]
 #![allow(non_upper_case_globals)]
 #![allow(non_camel_case_types)]
 #![allow(non_snake_case)]
 
 extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
 
 #[macro_use]
 extern crate log;
 
+#[cfg(not(feature = "std"))]
 mod ulibc;
 
+#[cfg(not(feature = "bindgen"))]
 pub mod bindings;
+/// Bindings generated for the build target by `build.rs`.
+#[cfg(feature = "bindgen")]
+pub mod bindings {
+    include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
+}
 pub mod blockdev;
 pub mod dir;
+pub mod error;
 pub mod file;
-
-use alloc::ffi::CString;
+#[cfg(feature = "std")]
+pub mod file_disk;
+pub mod fs;
+pub mod gpt;
+mod layout;
+pub mod mbr;
+pub mod mkfs;
+pub mod metadata;
+pub mod ram_disk;
+#[cfg(feature = "xattr")]
+pub mod xattr;
+mod mount;
 
 use bindings::{
-    ext4_dir_mv, ext4_dir_rm, ext4_flink, ext4_fremove, ext4_frename, ext4_fsymlink, ext4_inode_exist, ext4_mode_get, EOK
+    ext4_dir_mv, ext4_dir_rm, ext4_flink, ext4_fremove, ext4_frename, ext4_fsymlink, ext4_inode_exist, ext4_mknod,
+    ext4_mode_set, ext4_owner_get, ext4_owner_set, EOK
 };
 pub use blockdev::*;
-pub use dir::Ext4Dir;
+pub use dir::{DirEntry, Ext4Dir};
+pub use error::{Ext4Error, Ext4ErrorKind, Ext4Result};
 pub use file::{Ext4File, InodeTypes};
+#[cfg(feature = "std")]
+pub use file_disk::FileDisk;
+pub use fs::{DirHandle, Ext4Fs, FileHandle, Transaction};
+pub use gpt::{Gpt, GptPartition, Guid};
+pub use mbr::{mbr_scan, mbr_write, MbrPartition};
+pub use mkfs::{FsType, MkfsInfo, MkfsOptions};
+pub use metadata::{Metadata, SetTime, Timespec};
+pub use ram_disk::RamDisk;
+#[cfg(feature = "xattr")]
+pub use xattr::XattrFlags;
+
+use crate::error::to_cstring;
+use crate::mount::{resolve_path, same_volume};
 
 use crate::bindings::ext4_readlink;
 
@@ -43,67 +78,71 @@ use crate::bindings::ext4_readlink;
 /// EXT4_DE_SOCK
 /// EXT4_DE_SYMLINK
 pub fn lwext4_check_inode_exist(path: &str, types: InodeTypes) -> bool {
-    let c_path = CString::new(path).expect("CString::new failed");
+    let Ok(c_path) = resolve_path(path) else {
+        return false;
+    };
     let r = unsafe { ext4_inode_exist(c_path.as_ptr(), types as i32) }; // eg: types: EXT4_DE_REG_FILE
     r == EOK as i32
 }
 
 /// Rename directory
-pub fn lwext4_mvdir(path: &str, new_path: &str) -> Result<(), i32> {
-    let c_path = CString::new(path).expect("CString::new failed");
-    let c_new_path = CString::new(new_path).expect("CString::new failed");
+pub fn lwext4_mvdir(path: &str, new_path: &str) -> Ext4Result<()> {
+    same_volume(path, new_path, "ext4_dir_mv")?;
+    let c_path = resolve_path(path)?;
+    let c_new_path = resolve_path(new_path)?;
     let r = unsafe { ext4_dir_mv(c_path.as_ptr(), c_new_path.as_ptr()) };
     match r {
         0 => Ok(()),
         _ => {
             error!("ext4_dir_mv error: rc = {r}, path = {path}");
-            Err(r)
+            Err(Ext4Error::new(r, "ext4_dir_mv").with_path(path))
         }
     }
 }
 
 /// Rename file
-pub fn lwext4_mvfile(path: &str, new_path: &str) -> Result<(), i32> {
-    let c_path = CString::new(path).expect("CString::new failed");
-    let c_new_path = CString::new(new_path).expect("CString::new failed");
+pub fn lwext4_mvfile(path: &str, new_path: &str) -> Ext4Result<()> {
+    same_volume(path, new_path, "ext4_frename")?;
+    let c_path = resolve_path(path)?;
+    let c_new_path = resolve_path(new_path)?;
     let r = unsafe { ext4_frename(c_path.as_ptr(), c_new_path.as_ptr()) };
     match r {
         0 => Ok(()),
         _ => {
             error!("ext4_frename error: rc = {r}, path = {path}");
-            Err(r)
+            Err(Ext4Error::new(r, "ext4_frename").with_path(path))
         }
     }
 }
 
 /// Recursive directory remove
-pub fn lwext4_rmdir(path: &str) -> Result<(), i32> {
-    let c_path = CString::new(path).expect("CString::new failed");
+pub fn lwext4_rmdir(path: &str) -> Ext4Result<()> {
+    let c_path = resolve_path(path)?;
     let r = unsafe { ext4_dir_rm(c_path.as_ptr()) };
     match r {
         0 => Ok(()),
         e => {
             error!("ext4_dir_rm: rc = {r}, path = {path}");
-            Err(e)
+            Err(Ext4Error::new(e, "ext4_dir_rm").with_path(path))
         }
     }
 }
 
 /// Remove file by path.
-pub fn lwext4_rmfile(path: &str) -> Result<(), i32> {
-    let c_path = CString::new(path).expect("CString::new failed");
+pub fn lwext4_rmfile(path: &str) -> Ext4Result<()> {
+    let c_path = resolve_path(path)?;
     let r = unsafe { ext4_fremove(c_path.as_ptr()) };
     match r {
         0 => Ok(()),
         _ => {
             error!("ext4_fremove error: rc = {r}, path = {path}");
-            Err(r)
+            Err(Ext4Error::new(r, "ext4_fremove").with_path(path))
         }
     }
 }
 
-pub fn lwext4_readlink(path: &str, buf: &mut [u8]) -> Result<usize, i32> {
-    let c_path = CString::new(path).expect("CString::new failed");
+pub fn lwext4_readlink(path: &str, buf: &mut [u8]) -> Ext4Result<usize> {
+    let c_path = resolve_path(path)?;
     let mut r_cnt = 0;
     let r = unsafe {
         ext4_readlink(
@@ -117,33 +156,132 @@ pub fn lwext4_readlink(path: &str, buf: &mut [u8]) -> Result<usize, i32> {
         0 => Ok(r_cnt),
         _ => {
             error!("ext4_readlink: rc = {r}, path = {path}");
-            Err(r)
+            Err(Ext4Error::new(r, "ext4_readlink").with_path(path))
         }
     }
 }
 
-pub fn lwext4_symlink(target: &str, path: &str) -> Result<(), i32> {
-    let c_target = CString::new(target).expect("CString::new failed");
-    let c_path = CString::new(path).expect("CString::new failed");
+pub fn lwext4_symlink(target: &str, path: &str) -> Ext4Result<()> {
+    let c_target = to_cstring(target)?;
+    let c_path = resolve_path(path)?;
     let r = unsafe { ext4_fsymlink(c_target.as_ptr(), c_path.as_ptr()) };
     match r {
         0 => Ok(()),
         _ => {
             error!("ext4_fsymlink: rc = {r}, path = {path}");
-            Err(r)
+            Err(Ext4Error::new(r, "ext4_fsymlink").with_path(path))
         }
     }
 }
 
-pub fn lwext4_link(path: &str, hardlink_path: &str) -> Result<(), i32> {
-    let c_path = CString::new(path).expect("CString::new failed");
-    let c_hardlink_path = CString::new(hardlink_path).expect("CString::new failed");
+pub fn lwext4_link(path: &str, hardlink_path: &str) -> Ext4Result<()> {
+    same_volume(path, hardlink_path, "ext4_flink")?;
+    let c_path = resolve_path(path)?;
+    let c_hardlink_path = resolve_path(hardlink_path)?;
     let r = unsafe { ext4_flink(c_path.as_ptr(), c_hardlink_path.as_ptr()) };
     match r {
         0 => Ok(()),
         _ => {
             error!("ext4_flink: rc = {r}, path = {path}");
-            Err(r)
+            Err(Ext4Error::new(r, "ext4_flink").with_path(path))
+        }
+    }
+}
+
+/// Sets the permission bits (`0o7777`) of `path`, like `chmod`. The change
+/// time is updated if the volume has a [`MountOptions::clock`].
+pub fn lwext4_chmod(path: &str, mode: u32) -> Ext4Result<()> {
+    if mode & !0o7777 != 0 {
+        let err = Ext4Error::from_kind(Ext4ErrorKind::InvalidInput, "ext4_mode_set");
+        return Err(err.with_path(path));
+    }
+    let c_path = resolve_path(path)?;
+    let r = unsafe { ext4_mode_set(c_path.as_ptr(), mode) };
+    if r != EOK as i32 {
+        error!("ext4_mode_set: rc = {r}, path = {path}");
+        return Err(Ext4Error::new(r, "ext4_mode_set").with_path(path));
+    }
+    let (mp, ino) = metadata::lookup(path)?;
+    metadata::touch(mp, ino, "ext4_mode_set").map_err(|e| e.with_path(path))
+}
+
+/// Changes the owner and group of `path`, like `chown`. `None` leaves the id
+/// unchanged; it is read back first, not atomically with the update.
+pub fn lwext4_chown(path: &str, uid: Option<u32>, gid: Option<u32>) -> Ext4Result<()> {
+    let c_path = resolve_path(path)?;
+    let (mut old_uid, mut old_gid) = (0, 0);
+    if uid.is_none() || gid.is_none() {
+        let r = unsafe { ext4_owner_get(c_path.as_ptr(), &mut old_uid, &mut old_gid) };
+        if r != EOK as i32 {
+            error!("ext4_owner_get: rc = {r}, path = {path}");
+            return Err(Ext4Error::new(r, "ext4_owner_get").with_path(path));
+        }
+    }
+    let r = unsafe {
+        ext4_owner_set(
+            c_path.as_ptr(),
+            uid.unwrap_or(old_uid),
+            gid.unwrap_or(old_gid),
+        )
+    };
+    if r != EOK as i32 {
+        error!("ext4_owner_set: rc = {r}, path = {path}");
+        return Err(Ext4Error::new(r, "ext4_owner_set").with_path(path));
+    }
+    let (mp, ino) = metadata::lookup(path)?;
+    metadata::touch(mp, ino, "ext4_owner_set").map_err(|e| e.with_path(path))
+}
+
+/// Changes the access and modification times of `path`, like `utimensat`.
+/// [`SetTime::Now`] needs a [`MountOptions::clock`] and fails with
+/// [`Ext4ErrorKind::Unsupported`] without one.
+pub fn lwext4_utimens(path: &str, atime: SetTime, mtime: SetTime) -> Ext4Result<()> {
+    let (mp, ino) = metadata::lookup(path)?;
+    metadata::set_times(mp, ino, atime, mtime).map_err(|e| e.with_path(path))
+}
+
+/// Creates a device node, FIFO or socket at `path`, like `mknod`.
+///
+/// `kind` is one of the character device, block device, FIFO or socket
+/// [`InodeTypes`]; the device number is only stored for devices. `mode` are
+/// the permission bits (`0o7777`). lwext4 can not create the node with them,
+/// so they are set right after; if that fails the node is removed again.
+pub fn lwext4_mknod(
+    path: &str,
+    kind: InodeTypes,
+    mode: u32,
+    major: u32,
+    minor: u32,
+) -> Ext4Result<()> {
+    use InodeTypes::*;
+    let invalid = || Ext4Error::from_kind(Ext4ErrorKind::InvalidInput, "ext4_mknod").with_path(path);
+    let dev = metadata::encode_dev(major, minor);
+    let (filetype, dev) = match kind {
+        EXT4_DE_CHRDEV | EXT4_INODE_MODE_CHARDEV => (EXT4_DE_CHRDEV, dev),
+        EXT4_DE_BLKDEV | EXT4_INODE_MODE_BLOCKDEV => (EXT4_DE_BLKDEV, dev),
+        EXT4_DE_FIFO | EXT4_INODE_MODE_FIFO => (EXT4_DE_FIFO, 0),
+        EXT4_DE_SOCK | EXT4_INODE_MODE_SOCKET => (EXT4_DE_SOCK, 0),
+        _ => {
+            error!("ext4_mknod: {:?} is not a special file type, path = {}", kind, path);
+            return Err(invalid());
         }
+    };
+    // The device number must fit the 12-bit major and 20-bit minor of Linux.
+    if mode & !0o7777 != 0 || major > 0xfff || minor > 0xfffff {
+        return Err(invalid());
+    }
+    let c_path = resolve_path(path)?;
+    let r = unsafe { ext4_mknod(c_path.as_ptr(), filetype as i32, dev) };
+    if r != EOK as i32 {
+        error!("ext4_mknod: rc = {r}, path = {path}");
+        return Err(Ext4Error::new(r, "ext4_mknod").with_path(path));
     }
+    // ext4_mknod creates the node with default permissions; do not leave it
+    // behind with those if setting `mode` fails.
+    lwext4_chmod(path, mode).inspect_err(|_| {
+        let r = unsafe { ext4_fremove(c_path.as_ptr()) };
+        if r != EOK as i32 {
+            error!("ext4_fremove: rc = {r}, path = {path}");
+        }
+    })
 }
@@ -0,0 +1,120 @@
+use alloc::{vec, vec::Vec};
+use core::ffi::c_void;
+
+use crate::bindings::{ext4_mbr_bdevs, ext4_mbr_parts, ext4_mbr_scan, ext4_mbr_write, EOK};
+use crate::blockdev::{Ext4BlockWrapper, KernelDevOp};
+use crate::error::{Ext4Error, Ext4ErrorKind, Ext4Result};
+use crate::mkfs::{format_partition, MkfsOptions};
+
+/// Offset of the partition table in the MBR sector.
+const MBR_PART_TABLE_OFF: usize = 446;
+const MBR_PART_ENTRY_SIZE: usize = 16;
+
+/// A primary partition found in the master boot record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MbrPartition {
+    /// Index in the partition table, 0..4.
+    pub index: usize,
+    /// Partition type, e.g. `0x83` for Linux.
+    pub part_type: u8,
+    pub bootable: bool,
+    /// Start of the partition in bytes.
+    pub offset: u64,
+    /// Size of the partition in bytes.
+    pub size: u64,
+}
+
+/// Scans the MBR of `block_dev` with `ext4_mbr_scan` and returns its
+/// non-empty primary partitions.
+///
+/// A partition can be mounted with [`Ext4BlockWrapper::new_partition`]:
+///
+/// ```ignore
+/// let parts = mbr_scan::<Disk>(&mut disk)?;
+/// let p = parts.iter().find(|p| p.part_type == 0x83).unwrap();
+/// let fs = Ext4BlockWrapper::<Disk>::new_partition(disk, p.offset, p.size, options)?;
+/// ```
+pub fn mbr_scan<K: KernelDevOp>(block_dev: &mut K::DevType) -> Ext4Result<Vec<MbrPartition>> {
+    let bsize = Ext4BlockWrapper::<K>::sector_size(block_dev)?;
+    let devt_user = block_dev as *mut K::DevType as *mut c_void;
+    let mut bdev = Ext4BlockWrapper::<K>::alloc_blockdev(devt_user, bsize);
+    let bc = bdev.bc;
+    let mut bdevs: ext4_mbr_bdevs = unsafe { core::mem::zeroed() };
+    let r = unsafe { ext4_mbr_scan(&mut bdev, &mut bdevs) };
+    unsafe { Ext4BlockWrapper::<K>::free_blockdev(&bdev, bc) };
+    if r != EOK as i32 {
+        error!("ext4_mbr_scan: rc = {}", r);
+        return Err(Ext4Error::new(r, "ext4_mbr_scan"));
+    }
+
+    // lwext4 does not report the status and type bytes, read them directly.
+    let mut mbr = vec![0u8; bsize as usize];
+    K::read_blocks(block_dev, 0, &mut mbr).map_err(|e| {
+        error!("mbr_scan: failed to read the MBR: {}", e);
+        Ext4Error::from_kind(Ext4ErrorKind::Io, "mbr_scan")
+    })?;
+
+    let mut parts = Vec::new();
+    for (index, part) in bdevs.partitions.iter().enumerate() {
+        if part.part_size == 0 {
+            continue;
+        }
+        let entry = &mbr[MBR_PART_TABLE_OFF + index * MBR_PART_ENTRY_SIZE..];
+        parts.push(MbrPartition {
+            index,
+            part_type: entry[4],
+            bootable: entry[0] == 0x80,
+            offset: part.part_offset,
+            size: part.part_size,
+        });
+    }
+    debug!("mbr_scan: {:x?}", parts);
+    Ok(parts)
+}
+
+/// Writes a new MBR to `block_dev` with `ext4_mbr_write` and returns the
+/// resulting partitions.
+///
+/// `division` gives the size of each of the four primary partitions in
+/// percent of the disk; its sum must not exceed 100 and a 0 leaves the entry
+/// empty. All partitions get type `0x83`. With `format` set, every created
+/// partition is formatted with those options.
+///
+/// ```ignore
+/// let parts = mbr_write::<Disk>(&mut disk, [20, 80, 0, 0], 0x1234_5678, Some(&opts))?;
+/// ```
+pub fn mbr_write<K: KernelDevOp>(
+    block_dev: &mut K::DevType,
+    division: [u8; 4],
+    disk_id: u32,
+    format: Option<&MkfsOptions>,
+) -> Ext4Result<Vec<MbrPartition>> {
+    if division.iter().map(|&d| d as u32).sum::<u32>() > 100 {
+        error!("mbr_write: division {:?} exceeds 100%", division);
+        return Err(Ext4Error::from_kind(
+            Ext4ErrorKind::InvalidInput,
+            "ext4_mbr_write",
+        ));
+    }
+
+    let bsize = Ext4BlockWrapper::<K>::sector_size(block_dev)?;
+    let devt_user = block_dev as *mut K::DevType as *mut c_void;
+    let mut bdev = Ext4BlockWrapper::<K>::alloc_blockdev(devt_user, bsize);
+    let bc = bdev.bc;
+    let mut parts = ext4_mbr_parts { division };
+    let r = unsafe { ext4_mbr_write(&mut bdev, &mut parts, disk_id) };
+    unsafe { Ext4BlockWrapper::<K>::free_blockdev(&bdev, bc) };
+    if r != EOK as i32 {
+        error!("ext4_mbr_write: rc = {}", r);
+        return Err(Ext4Error::new(r, "ext4_mbr_write"));
+    }
+
+    let parts = mbr_scan::<K>(block_dev)?;
+    if let Some(options) = format {
+        for p in parts.iter() {
+            info!("mbr_write: formatting partition {}", p.index);
+            format_partition::<K>(block_dev, p.offset, p.size, options)?;
+        }
+    }
+    Ok(parts)
+}
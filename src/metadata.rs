@@ -0,0 +1,493 @@
+use core::ffi::{c_int, CStr};
+use core::mem::{size_of, MaybeUninit};
+use core::ptr;
+
+use crate::bindings::{
+    ext4_dir_close, ext4_dir_open, ext4_file, ext4_fs, ext4_fs_get_inode_ref,
+    ext4_fs_put_inode_ref, ext4_get_sblock, ext4_inode, ext4_inode_ref, ext4_mountpoint,
+    ext4_raw_inode_fill, ext4_sblock, EOK, EXT4_FRO_COM_HUGE_FILE, EXT4_GOOD_OLD_INODE_SIZE,
+    EXT4_INODE_FLAG_HUGE_FILE,
+};
+use crate::error::{Ext4Error, Ext4ErrorKind, Ext4Result};
+use crate::file::InodeTypes;
+use crate::mount::{self, resolve_mount_point, resolve_path};
+
+/// A point in time as stored in an inode: seconds since the Unix epoch and
+/// nanoseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct Timespec {
+    pub sec: i64,
+    pub nsec: u32,
+}
+
+impl Timespec {
+    /// Decodes an ext4 timestamp: a signed 32-bit seconds field, extended by
+    /// the two epoch bits and the nanoseconds of its `*_extra` field.
+    fn decode(sec: u32, extra: Option<u32>) -> Self {
+        let sec = u32::from_le(sec) as i32 as i64;
+        match extra.map(u32::from_le) {
+            Some(extra) => Self {
+                sec: sec + (((extra & 0b11) as i64) << 32),
+                nsec: extra >> 2,
+            },
+            None => Self { sec, nsec: 0 },
+        }
+    }
+
+    /// Encodes into the seconds field and, if the inode has one, the
+    /// `*_extra` field. Fails if the time can not be represented.
+    fn encode(self, extra: bool) -> Option<(u32, Option<u32>)> {
+        if self.nsec >= 1_000_000_000 {
+            return None;
+        }
+        let lo = self.sec as i32;
+        let epoch = (self.sec - lo as i64) >> 32;
+        match extra {
+            true if (0..=0b11).contains(&epoch) => Some((
+                (lo as u32).to_le(),
+                Some((epoch as u32 | self.nsec << 2).to_le()),
+            )),
+            false if epoch == 0 => Some(((lo as u32).to_le(), None)),
+            _ => None,
+        }
+    }
+}
+
+/// How a timestamp is changed, like the `timespec` passed to `utimensat`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetTime {
+    /// Set to the current time of the [`MountOptions::clock`]. Fails with
+    /// [`Ext4ErrorKind::Unsupported`] on a volume mounted without a clock.
+    ///
+    /// [`MountOptions::clock`]: crate::MountOptions::clock
+    Now,
+    /// Leave unchanged.
+    Omit,
+    /// Set to the given time.
+    At(Timespec),
+}
+
+/// Metadata of an inode, read from its on-disk structure.
+#[derive(Debug, Clone)]
+pub struct Metadata {
+    /// Inode number.
+    pub ino: u32,
+    /// One of the `EXT4_INODE_MODE_*` types.
+    pub file_type: InodeTypes,
+    /// Permission bits, e.g. `0o755`.
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    /// Size in bytes.
+    pub size: u64,
+    /// Allocated space in 512-byte units, like `st_blocks`.
+    pub blocks: u64,
+    /// Block size of the filesystem, like `st_blksize`.
+    pub blksize: u32,
+    /// Number of hard links.
+    pub nlink: u32,
+    /// Device number of a character or block device, otherwise 0.
+    pub rdev_major: u32,
+    pub rdev_minor: u32,
+    pub atime: Timespec,
+    pub mtime: Timespec,
+    pub ctime: Timespec,
+    /// Creation time, only recorded in inodes larger than 128 bytes.
+    pub crtime: Option<Timespec>,
+    /// `EXT4_INODE_FLAG_*` bits.
+    pub flags: u32,
+}
+
+impl Metadata {
+    /// Reads the metadata of the inode at the absolute `path`.
+    pub fn from_path(path: &str) -> Ext4Result<Self> {
+        let c_path = resolve_path(path)?;
+        let c_mp = resolve_mount_point(path)?;
+        let mut ino = 0;
+        let mut inode: ext4_inode = unsafe { core::mem::zeroed() };
+        let r = unsafe { ext4_raw_inode_fill(c_path.as_ptr(), &mut ino, &mut inode) };
+        if r != EOK as i32 {
+            error!("ext4_raw_inode_fill: rc = {}, path = {}", r, path);
+            return Err(Ext4Error::new(r, "ext4_raw_inode_fill").with_path(path));
+        }
+        let mut sb: *mut ext4_sblock = ptr::null_mut();
+        let r = unsafe { ext4_get_sblock(c_mp.as_ptr(), &mut sb) };
+        if r != EOK as i32 {
+            error!("ext4_get_sblock: rc = {}, path = {}", r, path);
+            return Err(Ext4Error::new(r, "ext4_get_sblock").with_path(path));
+        }
+        Ok(Self::from_raw(ino, &inode, unsafe { &*sb }))
+    }
+
+    /// Reads the metadata of the inode of an open file.
+    pub(crate) fn from_file(file: &ext4_file) -> Ext4Result<Self> {
+        unsafe {
+            with_inode(file.mp, file.inode, false, |inode, sb| {
+                Ok(Self::from_raw(file.inode, inode, sb))
+            })
+        }
+    }
+
+    fn from_raw(ino: u32, inode: &ext4_inode, sb: &ext4_sblock) -> Self {
+        let mode = u16::from_le(inode.mode) as u32;
+        let size = (u32::from_le(inode.size_hi) as u64) << 32 | u32::from_le(inode.size_lo) as u64;
+        let osd2 = unsafe { inode.osd2.linux2 };
+        let uid = (u16::from_le(osd2.uid_high) as u32) << 16 | u16::from_le(inode.uid) as u32;
+        let gid = (u16::from_le(osd2.gid_high) as u32) << 16 | u16::from_le(inode.gid) as u32;
+        let flags = u32::from_le(inode.flags);
+        let blksize = 1024u32 << u32::from_le(sb.log_block_size);
+
+        let mut blocks = u32::from_le(inode.blocks_count_lo) as u64;
+        if u32::from_le(sb.features_read_only) & EXT4_FRO_COM_HUGE_FILE != 0 {
+            blocks |= (u16::from_le(osd2.blocks_high) as u64) << 32;
+            if flags & EXT4_INODE_FLAG_HUGE_FILE != 0 {
+                // Counted in filesystem blocks instead of sectors.
+                blocks *= (blksize / 512) as u64;
+            }
+        }
+
+        let has = |end: usize| has_extra(inode, sb, end);
+        let extra = |offset: usize, value: u32| has(offset + 4).then_some(value);
+
+        let file_type: InodeTypes =
+            ((mode & InodeTypes::EXT4_INODE_MODE_TYPE_MASK as u32) as usize).into();
+        let (rdev_major, rdev_minor) = match file_type {
+            InodeTypes::EXT4_INODE_MODE_CHARDEV | InodeTypes::EXT4_INODE_MODE_BLOCKDEV => {
+                let blocks = inode.blocks;
+                decode_dev(u32::from_le(blocks[0]), u32::from_le(blocks[1]))
+            }
+            _ => (0, 0),
+        };
+
+        Self {
+            ino,
+            file_type,
+            mode: mode & 0o7777,
+            uid,
+            gid,
+            size,
+            blocks,
+            blksize,
+            nlink: u16::from_le(inode.links_count) as u32,
+            rdev_major,
+            rdev_minor,
+            atime: Timespec::decode(inode.access_time, extra(140, inode.atime_extra)),
+            mtime: Timespec::decode(inode.modification_time, extra(136, inode.mtime_extra)),
+            ctime: Timespec::decode(inode.change_inode_time, extra(132, inode.ctime_extra)),
+            crtime: has(148)
+                .then(|| Timespec::decode(inode.crtime, extra(148, inode.crtime_extra))),
+            flags,
+        }
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.file_type == InodeTypes::EXT4_INODE_MODE_DIRECTORY
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.file_type == InodeTypes::EXT4_INODE_MODE_FILE
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.file_type == InodeTypes::EXT4_INODE_MODE_SOFTLINK
+    }
+}
+
+/// Encodes a device number for `ext4_mknod` as Linux does: the old 16-bit
+/// format if it fits, otherwise the new 32-bit one. lwext4 stores the first
+/// in the first block pointer of the inode and the second in the second.
+pub(crate) fn encode_dev(major: u32, minor: u32) -> u32 {
+    if major < 256 && minor < 256 {
+        major << 8 | minor
+    } else {
+        (minor & 0xff) | (major & 0xfff) << 8 | (minor & !0xff) << 12
+    }
+}
+
+/// Decodes the device number from the first two block pointers of an inode.
+fn decode_dev(old: u32, new: u32) -> (u32, u32) {
+    if old != 0 {
+        ((old >> 8) & 0xff, old & 0xff)
+    } else {
+        ((new & 0xfff00) >> 8, (new & 0xff) | ((new >> 12) & 0xfff00))
+    }
+}
+
+/// Byte offsets of the `*_extra` timestamp fields in the inode.
+const CTIME_EXTRA: usize = 132;
+const MTIME_EXTRA: usize = 136;
+const ATIME_EXTRA: usize = 140;
+
+/// Size of the inodes on the volume.
+fn inode_size(sb: &ext4_sblock) -> usize {
+    if u32::from_le(sb.rev_level) == 0 {
+        EXT4_GOOD_OLD_INODE_SIZE as usize
+    } else {
+        u16::from_le(sb.inode_size) as usize
+    }
+}
+
+/// Whether the inode has the fields up to the byte offset `end`. Fields after
+/// the 128-byte base inode exist if the inode is large enough and its
+/// `extra_isize` covers them.
+fn has_extra(inode: &ext4_inode, sb: &ext4_sblock, end: usize) -> bool {
+    let base = EXT4_GOOD_OLD_INODE_SIZE as usize;
+    end <= size_of::<ext4_inode>()
+        && end <= inode_size(sb)
+        && end - base <= u16::from_le(inode.extra_isize) as usize
+}
+
+extern "C" {
+    // c/trans.c, the journal transactions of ext4.c.
+    fn lwext4_rust_trans_start(fs: *mut ext4_fs) -> c_int;
+    fn lwext4_rust_trans_stop(fs: *mut ext4_fs) -> c_int;
+    fn lwext4_rust_trans_abort(fs: *mut ext4_fs);
+}
+
+/// Runs `f` on the inode `ino` of the mounted volume `mp`, holding the mount
+/// lock. With `write` the changed inode is written back in a journal
+/// transaction, as lwext4 does for its own setters.
+///
+/// `f` works on a copy of the on-disk inode so that the fields a small inode
+/// lacks are never read or written.
+unsafe fn with_inode<T>(
+    mp: *mut ext4_mountpoint,
+    ino: u32,
+    write: bool,
+    f: impl FnOnce(&mut ext4_inode, &ext4_sblock) -> Ext4Result<T>,
+) -> Ext4Result<T> {
+    if write && (*mp).fs.read_only {
+        return Err(Ext4Error::from_kind(
+            Ext4ErrorKind::ReadOnly,
+            "ext4_fs_put_inode_ref",
+        ));
+    }
+    let locks = (*mp).os_locks.as_ref();
+    if let Some(lock) = locks.and_then(|l| l.lock) {
+        lock();
+    }
+    let ret = inode_update(mp, ino, write, f);
+    if let Some(unlock) = locks.and_then(|l| l.unlock) {
+        unlock();
+    }
+    ret
+}
+
+/// The body of [`with_inode`], called with the mount lock held.
+unsafe fn inode_update<T>(
+    mp: *mut ext4_mountpoint,
+    ino: u32,
+    write: bool,
+    f: impl FnOnce(&mut ext4_inode, &ext4_sblock) -> Ext4Result<T>,
+) -> Ext4Result<T> {
+    let fs = &mut (*mp).fs as *mut ext4_fs;
+    if write {
+        let r = lwext4_rust_trans_start(fs);
+        if r != EOK as i32 {
+            error!("ext4_trans_start: rc = {}, inode = {}", r, ino);
+            return Err(Ext4Error::new(r, "ext4_trans_start"));
+        }
+    }
+    let sb = &(*fs).sb;
+    let len = inode_size(sb).min(size_of::<ext4_inode>());
+    let mut inode_ref: ext4_inode_ref = core::mem::zeroed();
+    let r = ext4_fs_get_inode_ref(fs, ino, &mut inode_ref);
+    if r != EOK as i32 {
+        if write {
+            lwext4_rust_trans_abort(fs);
+        }
+        error!("ext4_fs_get_inode_ref: rc = {}, inode = {}", r, ino);
+        return Err(Ext4Error::new(r, "ext4_fs_get_inode_ref"));
+    }
+    let mut inode: ext4_inode = core::mem::zeroed();
+    ptr::copy_nonoverlapping(
+        inode_ref.inode as *const u8,
+        &mut inode as *mut _ as *mut u8,
+        len,
+    );
+    let ret = f(&mut inode, sb);
+    if write && ret.is_ok() {
+        ptr::copy_nonoverlapping(
+            &inode as *const _ as *const u8,
+            inode_ref.inode as *mut u8,
+            len,
+        );
+        inode_ref.dirty = true;
+    }
+    let r = ext4_fs_put_inode_ref(&mut inode_ref);
+    if r != EOK as i32 {
+        if write {
+            lwext4_rust_trans_abort(fs);
+        }
+        error!("ext4_fs_put_inode_ref: rc = {}, inode = {}", r, ino);
+        return Err(Ext4Error::new(r, "ext4_fs_put_inode_ref"));
+    }
+    if write && ret.is_ok() {
+        let r = lwext4_rust_trans_stop(fs);
+        if r != EOK as i32 {
+            error!("ext4_trans_stop: rc = {}, inode = {}", r, ino);
+            return Err(Ext4Error::new(r, "ext4_trans_stop"));
+        }
+    } else if write {
+        lwext4_rust_trans_abort(fs);
+    }
+    ret
+}
+
+/// Finds the volume and inode number of the absolute `path`.
+pub(crate) fn lookup(path: &str) -> Ext4Result<(*mut ext4_mountpoint, u32)> {
+    let c_path = resolve_path(path)?;
+    let mut ino = 0;
+    let mut inode: ext4_inode = unsafe { core::mem::zeroed() };
+    let r = unsafe { ext4_raw_inode_fill(c_path.as_ptr(), &mut ino, &mut inode) };
+    if r != EOK as i32 {
+        error!("ext4_raw_inode_fill: rc = {}, path = {}", r, path);
+        return Err(Ext4Error::new(r, "ext4_raw_inode_fill").with_path(path));
+    }
+    // The mount point handle is only reachable through an open handle.
+    let c_mp = resolve_mount_point(path)?;
+    let mut dir = MaybeUninit::uninit();
+    let r = unsafe { ext4_dir_open(dir.as_mut_ptr(), c_mp.as_ptr()) };
+    if r != EOK as i32 {
+        error!("ext4_dir_open: rc = {}, path = {}", r, path);
+        return Err(Ext4Error::new(r, "ext4_dir_open").with_path(path));
+    }
+    let mut dir = unsafe { dir.assume_init() };
+    let mp = dir.f.mp;
+    unsafe { ext4_dir_close(&mut dir) };
+    Ok((mp, ino))
+}
+
+/// Current time of the clock of the volume `mp`, if it has one.
+unsafe fn now(mp: *mut ext4_mountpoint) -> Option<Timespec> {
+    let name = CStr::from_ptr((*mp).name.as_ptr()).to_str().ok()?;
+    mount::clock(name).map(|clock| clock())
+}
+
+/// Stores `time` into a timestamp and its `*_extra` field at `extra_offset`.
+fn store_time(
+    inode: &mut ext4_inode,
+    sb: &ext4_sblock,
+    time: Timespec,
+    extra_offset: usize,
+    op: &'static str,
+) -> Ext4Result<(u32, Option<u32>)> {
+    time.encode(has_extra(inode, sb, extra_offset + 4))
+        .ok_or_else(|| Ext4Error::from_kind(Ext4ErrorKind::InvalidInput, op))
+}
+
+/// Sets the change time to now, if the volume has a clock.
+fn touch_ctime(
+    inode: &mut ext4_inode,
+    sb: &ext4_sblock,
+    now: Option<Timespec>,
+    op: &'static str,
+) -> Ext4Result<()> {
+    if let Some(now) = now {
+        let (sec, extra) = store_time(inode, sb, now, CTIME_EXTRA, op)?;
+        inode.change_inode_time = sec;
+        if let Some(extra) = extra {
+            inode.ctime_extra = extra;
+        }
+    }
+    Ok(())
+}
+
+/// Sets the change time of the inode `ino` to now, if the volume has a
+/// clock. Used after the lwext4 setters, which leave it alone.
+pub(crate) fn touch(mp: *mut ext4_mountpoint, ino: u32, op: &'static str) -> Ext4Result<()> {
+    unsafe {
+        match now(mp) {
+            Some(now) => with_inode(mp, ino, true, |inode, sb| {
+                touch_ctime(inode, sb, Some(now), op)
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Sets the permission bits (`0o7777`) of the inode `ino`, like `chmod`.
+pub(crate) fn set_permissions(mp: *mut ext4_mountpoint, ino: u32, mode: u32) -> Ext4Result<()> {
+    const OP: &str = "ext4_mode_set";
+    if mode & !0o7777 != 0 {
+        return Err(Ext4Error::from_kind(Ext4ErrorKind::InvalidInput, OP));
+    }
+    unsafe {
+        let now = now(mp);
+        with_inode(mp, ino, true, |inode, sb| {
+            touch_ctime(inode, sb, now, OP)?;
+            let old = u16::from_le(inode.mode);
+            inode.mode = (old & !0o7777 | mode as u16).to_le();
+            Ok(())
+        })
+    }
+}
+
+/// Changes the owner and group of the inode `ino`, like `chown`. `None`
+/// leaves the id unchanged.
+pub(crate) fn set_owner(
+    mp: *mut ext4_mountpoint,
+    ino: u32,
+    uid: Option<u32>,
+    gid: Option<u32>,
+) -> Ext4Result<()> {
+    const OP: &str = "ext4_owner_set";
+    unsafe {
+        let now = now(mp);
+        with_inode(mp, ino, true, |inode, sb| {
+            touch_ctime(inode, sb, now, OP)?;
+            if let Some(uid) = uid {
+                inode.uid = (uid as u16).to_le();
+                inode.osd2.linux2.uid_high = ((uid >> 16) as u16).to_le();
+            }
+            if let Some(gid) = gid {
+                inode.gid = (gid as u16).to_le();
+                inode.osd2.linux2.gid_high = ((gid >> 16) as u16).to_le();
+            }
+            Ok(())
+        })
+    }
+}
+
+/// Changes the access and modification times of the inode `ino`, like
+/// `utimensat`. The change time is set to now unless both are omitted.
+pub(crate) fn set_times(
+    mp: *mut ext4_mountpoint,
+    ino: u32,
+    atime: SetTime,
+    mtime: SetTime,
+) -> Ext4Result<()> {
+    const OP: &str = "ext4_time_set";
+    if atime == SetTime::Omit && mtime == SetTime::Omit {
+        return Ok(());
+    }
+    unsafe {
+        let now = now(mp);
+        let resolve = |time: SetTime| match time {
+            SetTime::Now => now
+                .map(Some)
+                .ok_or_else(|| Ext4Error::from_kind(Ext4ErrorKind::Unsupported, OP)),
+            SetTime::Omit => Ok(None),
+            SetTime::At(time) => Ok(Some(time)),
+        };
+        let atime = resolve(atime)?;
+        let mtime = resolve(mtime)?;
+        with_inode(mp, ino, true, |inode, sb| {
+            if let Some(time) = atime {
+                let (sec, extra) = store_time(inode, sb, time, ATIME_EXTRA, OP)?;
+                inode.access_time = sec;
+                if let Some(extra) = extra {
+                    inode.atime_extra = extra;
+                }
+            }
+            if let Some(time) = mtime {
+                let (sec, extra) = store_time(inode, sb, time, MTIME_EXTRA, OP)?;
+                inode.modification_time = sec;
+                if let Some(extra) = extra {
+                    inode.mtime_extra = extra;
+                }
+            }
+            touch_ctime(inode, sb, now, OP)
+        })
+    }
+}
@@ -0,0 +1,242 @@
+use alloc::{boxed::Box, string::String};
+use core::ffi::c_void;
+use core::ptr::null;
+
+use crate::bindings::*;
+use crate::blockdev::{Ext4BlockWrapper, KernelDevOp};
+use crate::error::{to_cstring, Ext4Error, Ext4ErrorKind, Ext4Result};
+
+/// Filesystem revision created by [`format`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsType {
+    Ext2 = F_SET_EXT2 as isize,
+    Ext3 = F_SET_EXT3 as isize,
+    Ext4 = F_SET_EXT4 as isize,
+}
+
+/// Options of [`format`]. Every value left unset is chosen by lwext4.
+///
+/// There is no option for the feature flags: `ext4_mkfs` overwrites them
+/// with the set lwext4 supports for the [`FsType`], the only one it knows
+/// how to lay out. They are reported back in [`MkfsInfo`].
+#[derive(Debug, Clone)]
+pub struct MkfsOptions {
+    fs_type: FsType,
+    block_size: u32,
+    inode_size: u32,
+    inode_ratio: u32,
+    journal: Option<bool>,
+    journal_size: u64,
+    label: Option<String>,
+    uuid: [u8; 16],
+}
+
+impl MkfsOptions {
+    pub fn new(fs_type: FsType) -> Self {
+        Self {
+            fs_type,
+            block_size: 0,
+            inode_size: 0,
+            inode_ratio: 0,
+            journal: None,
+            journal_size: 0,
+            label: None,
+            uuid: [0; 16],
+        }
+    }
+
+    /// Block size in bytes: 1024, 2048, 4096, ... 65536. Default 4096.
+    pub fn block_size(mut self, size: u32) -> Self {
+        self.block_size = size;
+        self
+    }
+
+    /// On-disk inode size in bytes: 128, 256, ... up to the block size.
+    /// Default 256.
+    pub fn inode_size(mut self, size: u32) -> Self {
+        self.inode_size = size;
+        self
+    }
+
+    /// Bytes of volume per inode, e.g. 16384.
+    pub fn inode_ratio(mut self, bytes_per_inode: u32) -> Self {
+        self.inode_ratio = bytes_per_inode;
+        self
+    }
+
+    /// Create a journal. Defaults to on for ext3/ext4 and off for ext2.
+    pub fn journal(mut self, on: bool) -> Self {
+        self.journal = Some(on);
+        self
+    }
+
+    /// Journal size in bytes.
+    pub fn journal_size(mut self, bytes: u64) -> Self {
+        self.journal_size = bytes;
+        self
+    }
+
+    /// Volume label, at most 16 bytes.
+    pub fn label(mut self, label: &str) -> Self {
+        self.label = Some(String::from(label));
+        self
+    }
+
+    /// Filesystem UUID. All zero unless set, since no randomness is
+    /// available here.
+    pub fn uuid(mut self, uuid: [u8; 16]) -> Self {
+        self.uuid = uuid;
+        self
+    }
+
+    fn validate(&self) -> Ext4Result<()> {
+        let bs = self.block_size;
+        let is = self.inode_size;
+        let bad_bs = bs != 0 && (!bs.is_power_of_two() || !(1024..=65536).contains(&bs));
+        let bad_is = is != 0
+            && (!is.is_power_of_two() || is < EXT4_GOOD_OLD_INODE_SIZE || is > bs.max(4096));
+        let bad_journal = self.fs_type == FsType::Ext2 && self.journal == Some(true);
+        let bad_label = self.label.as_ref().is_some_and(|l| l.len() > 16);
+        if bad_bs || bad_is || bad_journal || bad_label {
+            error!("mkfs: invalid options {:?}", self);
+            return Err(Ext4Error::from_kind(
+                Ext4ErrorKind::InvalidInput,
+                "ext4_mkfs",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Geometry and features of a filesystem, as laid out by [`format`] or read
+/// back by [`read_info`].
+#[derive(Debug, Clone)]
+pub struct MkfsInfo {
+    /// Size of the filesystem in bytes.
+    pub len: u64,
+    pub block_size: u32,
+    pub blocks_per_group: u32,
+    pub inodes_per_group: u32,
+    pub inode_size: u32,
+    pub inodes: u32,
+    pub journal_blocks: u32,
+    pub feat_compat: u32,
+    pub feat_incompat: u32,
+    pub feat_ro_compat: u32,
+    pub uuid: [u8; 16],
+}
+
+impl From<&ext4_mkfs_info> for MkfsInfo {
+    fn from(info: &ext4_mkfs_info) -> Self {
+        Self {
+            len: info.len,
+            block_size: info.block_size,
+            blocks_per_group: info.blocks_per_group,
+            inodes_per_group: info.inodes_per_group,
+            inode_size: info.inode_size,
+            inodes: info.inodes,
+            journal_blocks: info.journal_blocks,
+            feat_compat: info.feat_compat,
+            feat_incompat: info.feat_incompat,
+            feat_ro_compat: info.feat_ro_compat,
+            uuid: info.uuid,
+        }
+    }
+}
+
+/// Formats the whole of `block_dev` with `ext4_mkfs`.
+///
+/// ```ignore
+/// let opts = MkfsOptions::new(FsType::Ext4).block_size(4096).label("rootfs");
+/// format::<Disk>(&mut disk, &opts)?;
+/// let fs = Ext4BlockWrapper::<Disk>::new(disk)?;
+/// ```
+pub fn format<K: KernelDevOp>(
+    block_dev: &mut K::DevType,
+    options: &MkfsOptions,
+) -> Ext4Result<MkfsInfo> {
+    format_partition::<K>(block_dev, 0, 0, options)
+}
+
+/// Formats the partition of `block_dev` starting at byte `part_offset` and
+/// `part_size` bytes long. A `part_size` of 0 stands for the whole device.
+pub fn format_partition<K: KernelDevOp>(
+    block_dev: &mut K::DevType,
+    part_offset: u64,
+    part_size: u64,
+    options: &MkfsOptions,
+) -> Ext4Result<MkfsInfo> {
+    options.validate()?;
+
+    let len = if part_size != 0 {
+        part_size
+    } else {
+        K::size(block_dev).map_err(|e| {
+            error!("mkfs: failed to get the device size: {}", e);
+            Ext4Error::from_kind(Ext4ErrorKind::Io, "ext4_mkfs")
+        })?
+    };
+    let block_size = if options.block_size != 0 {
+        options.block_size
+    } else {
+        4096
+    };
+    let journal = options.journal.unwrap_or(options.fs_type != FsType::Ext2);
+
+    let mut info: ext4_mkfs_info = unsafe { core::mem::zeroed() };
+    info.len = len;
+    info.block_size = options.block_size;
+    info.inode_size = options.inode_size;
+    if options.inode_ratio != 0 {
+        info.inodes = (len / options.inode_ratio as u64).min(u32::MAX as u64) as u32;
+    }
+    info.journal = journal;
+    info.journal_blocks = (options.journal_size / block_size as u64) as u32;
+    info.uuid = options.uuid;
+    let label = match &options.label {
+        Some(l) => Some(to_cstring(l)?),
+        None => None,
+    };
+    info.label = label.as_ref().map_or(null(), |l| l.as_ptr());
+
+    let bsize = Ext4BlockWrapper::<K>::sector_size(block_dev)?;
+    let devt_user = block_dev as *mut K::DevType as *mut c_void;
+    let mut bdev = Ext4BlockWrapper::<K>::alloc_blockdev(devt_user, bsize);
+    // ext4_mkfs binds its own block cache, keep ours to release it.
+    let bc = bdev.bc;
+    bdev.part_offset = part_offset;
+    bdev.part_size = part_size;
+    let mut fs: Box<ext4_fs> = Box::new(unsafe { core::mem::zeroed() });
+
+    let r = unsafe { ext4_mkfs(fs.as_mut(), &mut bdev, &mut info, options.fs_type as i32) };
+    unsafe { Ext4BlockWrapper::<K>::free_blockdev(&bdev, bc) };
+    if r != EOK as i32 {
+        error!("ext4_mkfs: rc = {}", r);
+        return Err(Ext4Error::new(r, "ext4_mkfs"));
+    }
+    info!("mkfs: {:?} {} bytes", options.fs_type, info.len);
+    Ok(MkfsInfo::from(&info))
+}
+
+/// Reads the geometry and features of an existing filesystem on the
+/// partition of `block_dev`, using `ext4_mkfs_read_info`.
+pub fn read_info<K: KernelDevOp>(
+    block_dev: &mut K::DevType,
+    part_offset: u64,
+    part_size: u64,
+) -> Ext4Result<MkfsInfo> {
+    let bsize = Ext4BlockWrapper::<K>::sector_size(block_dev)?;
+    let devt_user = block_dev as *mut K::DevType as *mut c_void;
+    let mut bdev = Ext4BlockWrapper::<K>::alloc_blockdev(devt_user, bsize);
+    let bc = bdev.bc;
+    bdev.part_offset = part_offset;
+    bdev.part_size = part_size;
+    let mut info: ext4_mkfs_info = unsafe { core::mem::zeroed() };
+    let r = unsafe { ext4_mkfs_read_info(&mut bdev, &mut info) };
+    unsafe { Ext4BlockWrapper::<K>::free_blockdev(&bdev, bc) };
+    if r != EOK as i32 {
+        error!("ext4_mkfs_read_info: rc = {}", r);
+        return Err(Ext4Error::new(r, "ext4_mkfs_read_info"));
+    }
+    Ok(MkfsInfo::from(&info))
+}
@@ -0,0 +1,154 @@
+//! Routing of absolute paths to the mounted volumes.
+//!
+//! lwext4 picks the first mount point that is a prefix of a path, so nested
+//! mount points such as `/` and `/data/` can not be registered with lwext4
+//! directly. Every volume is therefore mounted in lwext4 under an internal,
+//! non-overlapping name (`/ext4_fs<id>/`), and paths are translated here by
+//! the longest matching caller-chosen mount point.
+
+use alloc::{ffi::CString, format, string::String, vec::Vec};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::{Mutex, MutexGuard};
+
+use crate::bindings::ext4_blockdev;
+use crate::error::{to_cstring, Ext4Error, Ext4ErrorKind, Ext4Result};
+use crate::metadata::Timespec;
+
+/// Flushes the device behind an lwext4 block device, returning an errno.
+pub(crate) type DevFlushFn = unsafe fn(*mut ext4_blockdev) -> i32;
+
+/// Current time of the OS, see [`crate::MountOptions::clock`].
+pub(crate) type ClockFn = fn() -> Timespec;
+
+struct MountEntry {
+    id: usize,
+    /// Mount point chosen by the caller, e.g. `/data/`.
+    mount_point: String,
+    /// Device flush of the volume, for handles that do not know its type.
+    flush: DevFlushFn,
+    clock: Option<ClockFn>,
+}
+
+static MOUNTS: Mutex<Vec<MountEntry>> = Mutex::new(Vec::new());
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Serializes the lwext4 calls that change its static device and mount
+/// tables: `ext4_device_register`, `ext4_mount`, `ext4_umount` and
+/// `ext4_device_unregister`. The [`crate::Ext4Locking`] callbacks do not cover
+/// them, as they are installed only after `ext4_mount`.
+static LWEXT4_TABLES: Mutex<()> = Mutex::new(());
+
+pub(crate) fn lock_tables() -> MutexGuard<'static, ()> {
+    LWEXT4_TABLES.lock()
+}
+
+/// Name the volume `id` is registered with as an lwext4 block device.
+pub(crate) fn device_name(id: usize) -> String {
+    format!("ext4_fs{}", id)
+}
+
+/// Name the volume `id` is mounted at inside lwext4.
+pub(crate) fn internal_mount_point(id: usize) -> String {
+    format!("/ext4_fs{}/", id)
+}
+
+/// Reserves `mount_point` for a new volume and returns its id.
+pub(crate) fn register(
+    mount_point: &str,
+    flush: DevFlushFn,
+    clock: Option<ClockFn>,
+) -> Ext4Result<usize> {
+    let mut mounts = MOUNTS.lock();
+    if mounts.iter().any(|m| m.mount_point == mount_point) {
+        error!("mount point {} is already in use", mount_point);
+        return Err(Ext4Error::from_kind(Ext4ErrorKind::Busy, "ext4_mount").with_path(mount_point));
+    }
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    mounts.push(MountEntry {
+        id,
+        mount_point: String::from(mount_point),
+        flush,
+        clock,
+    });
+    Ok(id)
+}
+
+pub(crate) fn unregister(id: usize) {
+    MOUNTS.lock().retain(|m| m.id != id);
+}
+
+/// Returns the device flush of the volume mounted inside lwext4 at
+/// `internal`, see [`internal_mount_point`].
+pub(crate) fn device_flush(internal: &str) -> Option<DevFlushFn> {
+    MOUNTS
+        .lock()
+        .iter()
+        .find(|m| internal_mount_point(m.id) == internal)
+        .map(|m| m.flush)
+}
+
+/// Returns the clock of the volume mounted inside lwext4 at `internal`.
+pub(crate) fn clock(internal: &str) -> Option<ClockFn> {
+    MOUNTS
+        .lock()
+        .iter()
+        .find(|m| internal_mount_point(m.id) == internal)
+        .and_then(|m| m.clock)
+}
+
+/// Finds the volume `path` belongs to, returning its id and the remainder of
+/// the path relative to its mount point.
+fn route(path: &str) -> Option<(usize, &str)> {
+    let mounts = MOUNTS.lock();
+    let mut best: Option<(usize, usize)> = None;
+    for m in mounts.iter() {
+        let mp = m.mount_point.as_str();
+        // `/data` refers to the root of the volume mounted at `/data/`.
+        let matched = path.starts_with(mp) || path == mp.trim_end_matches('/');
+        let longer = match best {
+            Some((_, len)) => mp.len() > len,
+            None => true,
+        };
+        if matched && longer {
+            best = Some((m.id, mp.len()));
+        }
+    }
+    best.map(|(id, len)| (id, path.get(len..).unwrap_or("")))
+}
+
+/// Returns the id of the volume that `path` belongs to.
+pub(crate) fn volume_of(path: &str) -> Ext4Result<usize> {
+    route(path).map(|(id, _)| id).ok_or_else(|| {
+        Ext4Error::from_kind(Ext4ErrorKind::NotFound, "ext4_get_mount").with_path(path)
+    })
+}
+
+/// Returns the name lwext4 mounted the volume of `path` at.
+pub(crate) fn resolve_mount_point(path: &str) -> Ext4Result<CString> {
+    let id = volume_of(path)?;
+    to_cstring(&internal_mount_point(id))
+}
+
+/// Checks that `path` and `new_path` are on the same volume.
+pub(crate) fn same_volume(path: &str, new_path: &str, op: &'static str) -> Ext4Result<()> {
+    if volume_of(path)? != volume_of(new_path)? {
+        error!("{}: {} and {} are on different volumes", op, path, new_path);
+        return Err(Ext4Error::from_kind(Ext4ErrorKind::CrossDevice, op).with_path(new_path));
+    }
+    Ok(())
+}
+
+/// Translates an absolute path into the path lwext4 knows it by.
+pub(crate) fn resolve_path(path: &str) -> Ext4Result<CString> {
+    match route(path) {
+        Some((id, rest)) => {
+            let mut internal = internal_mount_point(id);
+            internal.push_str(rest.trim_start_matches('/'));
+            to_cstring(&internal).map_err(|e| e.with_path(path))
+        }
+        None => {
+            error!("no ext4 volume mounted for path {}", path);
+            Err(Ext4Error::from_kind(Ext4ErrorKind::NotFound, "ext4_get_mount").with_path(path))
+        }
+    }
+}
@@ -0,0 +1,126 @@
+//! A block device held in memory.
+
+use alloc::{borrow::Cow, vec, vec::Vec};
+
+use crate::bindings::EIO;
+use crate::blockdev::BlockDevice;
+use crate::error::{Ext4Error, Ext4ErrorKind, Ext4Result};
+
+/// Default sector size of a [`RamDisk`].
+const RAM_DISK_BSIZE: u32 = 512;
+
+/// A disk in memory, either allocated or wrapped around an embedded image.
+///
+/// An embedded image is only read until the first write, which copies it
+/// into an owned buffer.
+///
+/// ```ignore
+/// static IMAGE: &[u8] = include_bytes!("initrd.img");
+/// let fs = Ext4Fs::<RamDisk>::new(RamDisk::from_static(IMAGE), MountOptions::new())?;
+///
+/// let mut disk = RamDisk::new(16 << 20);
+/// mkfs::format::<RamDisk>(&mut disk, &MkfsOptions::new(FsType::Ext4))?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct RamDisk {
+    data: Cow<'static, [u8]>,
+    block_size: u32,
+}
+
+impl RamDisk {
+    /// Allocates a zeroed disk of `size` bytes.
+    pub fn new(size: usize) -> Self {
+        Self::from_vec(vec![0u8; size])
+    }
+
+    /// Uses `data` as the disk contents.
+    pub fn from_vec(data: Vec<u8>) -> Self {
+        Self {
+            data: Cow::Owned(data),
+            block_size: RAM_DISK_BSIZE,
+        }
+    }
+
+    /// Wraps an embedded image, e.g. from `include_bytes!`, copying it on
+    /// the first write.
+    pub fn from_static(image: &'static [u8]) -> Self {
+        Self {
+            data: Cow::Borrowed(image),
+            block_size: RAM_DISK_BSIZE,
+        }
+    }
+
+    /// Uses sectors of `block_size` bytes instead of 512. Fails with
+    /// [`Ext4ErrorKind::InvalidInput`] unless it is a power of two.
+    pub fn with_block_size(mut self, block_size: u32) -> Ext4Result<Self> {
+        if !block_size.is_power_of_two() {
+            error!("RamDisk: invalid block size {}", block_size);
+            return Err(Ext4Error::from_kind(
+                Ext4ErrorKind::InvalidInput,
+                "ram_disk",
+            ));
+        }
+        self.block_size = block_size;
+        Ok(self)
+    }
+
+    /// Whether the disk still reads from the embedded image.
+    pub fn is_borrowed(&self) -> bool {
+        matches!(self.data, Cow::Borrowed(_))
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Returns the disk contents, copying an untouched embedded image.
+    pub fn into_vec(self) -> Vec<u8> {
+        self.data.into_owned()
+    }
+
+    /// Byte range of `count` sectors at `lba`, if it is inside the disk and
+    /// matches a buffer of `len` bytes.
+    fn range(&self, lba: u64, count: u32, len: usize) -> Result<(usize, usize), i32> {
+        let bsize = self.block_size as u64;
+        let start = lba.checked_mul(bsize);
+        let end = start.and_then(|s| s.checked_add(count as u64 * bsize));
+        match (start, end) {
+            (Some(s), Some(e)) if e <= self.data.len() as u64 && (e - s) as usize == len => {
+                Ok((s as usize, e as usize))
+            }
+            _ => {
+                error!(
+                    "RamDisk: bad request lba {} count {} len {}",
+                    lba, count, len
+                );
+                Err(EIO as i32)
+            }
+        }
+    }
+}
+
+impl BlockDevice for RamDisk {
+    fn read_blocks(&mut self, lba: u64, count: u32, buf: &mut [u8]) -> Result<(), i32> {
+        let (start, end) = self.range(lba, count, buf.len())?;
+        buf.copy_from_slice(&self.data[start..end]);
+        Ok(())
+    }
+
+    fn write_blocks(&mut self, lba: u64, count: u32, buf: &[u8]) -> Result<(), i32> {
+        let (start, end) = self.range(lba, count, buf.len())?;
+        self.data.to_mut()[start..end].copy_from_slice(buf);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), i32> {
+        Ok(())
+    }
+
+    fn num_blocks(&mut self) -> u64 {
+        self.data.len() as u64 / self.block_size as u64
+    }
+
+    fn block_size(&mut self) -> u32 {
+        self.block_size
+    }
+}
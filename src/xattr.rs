@@ -0,0 +1,187 @@
+//! Extended attributes, stored by lwext4 in the inode body or an attribute
+//! block.
+//!
+//! Names carry their namespace as a prefix: `user.`, `trusted.`, `security.`
+//! or `system.`.
+
+use alloc::{string::String, vec, vec::Vec};
+
+use crate::bindings::{
+    ext4_getxattr, ext4_listxattr, ext4_removexattr, ext4_setxattr, ENODATA, EOK, ERANGE,
+};
+use crate::error::{Ext4Error, Ext4ErrorKind, Ext4Result};
+use crate::mount::resolve_path;
+
+/// Namespaces lwext4 knows how to store.
+const NAMESPACES: [&str; 4] = ["user.", "trusted.", "security.", "system."];
+
+/// Largest value or name list handled, as `XATTR_SIZE_MAX` on Linux.
+const XATTR_SIZE_MAX: usize = 65536;
+
+/// Longest attribute name without its namespace prefix.
+const XATTR_NAME_MAX: usize = 255;
+
+/// How [`set_xattr`] treats an existing attribute, like the `XATTR_CREATE`
+/// and `XATTR_REPLACE` flags of `setxattr`.
+///
+/// lwext4 has no such flags, so the check is a separate lookup before the
+/// attribute is set and is not atomic: a concurrent `set_xattr` or
+/// `remove_xattr` on the same file can change the outcome in between.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum XattrFlags {
+    /// Create the attribute or replace its value.
+    #[default]
+    Any,
+    /// Fail with [`Ext4ErrorKind::Exists`] if the attribute exists.
+    Create,
+    /// Fail with [`Ext4ErrorKind::NoData`] if the attribute does not exist.
+    Replace,
+}
+
+/// Checks that `name` has a known namespace and fits on disk.
+fn check_name(name: &str, op: &'static str) -> Ext4Result<()> {
+    let valid = NAMESPACES.iter().any(|ns| {
+        name.strip_prefix(ns)
+            .is_some_and(|rest| !rest.is_empty() && rest.len() <= XATTR_NAME_MAX)
+    });
+    if !valid || name.contains('\0') {
+        error!("{}: invalid attribute name {}", op, name);
+        return Err(Ext4Error::from_kind(Ext4ErrorKind::InvalidInput, op));
+    }
+    Ok(())
+}
+
+/// Calls `f` with a buffer grown until the result fits. `f` returns an errno
+/// and the size the result needs.
+fn read_sized(mut f: impl FnMut(&mut [u8]) -> (i32, usize)) -> Result<Vec<u8>, i32> {
+    let mut buf = vec![0u8; 256];
+    loop {
+        let (r, size) = f(&mut buf);
+        let too_small = (r == EOK as i32 && size > buf.len()) || r == ERANGE as i32;
+        if too_small && buf.len() < XATTR_SIZE_MAX {
+            let len = size.max(buf.len() * 2).min(XATTR_SIZE_MAX);
+            buf.resize(len, 0);
+            continue;
+        }
+        if r != EOK as i32 {
+            return Err(r);
+        }
+        buf.truncate(size);
+        return Ok(buf);
+    }
+}
+
+/// Reads the value of the attribute `name` of `path`.
+pub fn get_xattr(path: &str, name: &str) -> Ext4Result<Vec<u8>> {
+    check_name(name, "ext4_getxattr").map_err(|e| e.with_path(path))?;
+    let c_path = resolve_path(path)?;
+    read_sized(|buf| {
+        let mut size = 0;
+        let r = unsafe {
+            ext4_getxattr(
+                c_path.as_ptr(),
+                name.as_ptr() as _,
+                name.len(),
+                buf.as_mut_ptr() as _,
+                buf.len(),
+                &mut size,
+            )
+        };
+        (r, size)
+    })
+    .map_err(|r| {
+        if r != ENODATA as i32 {
+            error!(
+                "ext4_getxattr: rc = {}, path = {}, name = {}",
+                r, path, name
+            );
+        }
+        Ext4Error::new(r, "ext4_getxattr").with_path(path)
+    })
+}
+
+/// Sets the attribute `name` of `path` to `value`. `flags` are checked
+/// before setting, not atomically with it; see [`XattrFlags`].
+pub fn set_xattr(path: &str, name: &str, value: &[u8], flags: XattrFlags) -> Ext4Result<()> {
+    check_name(name, "ext4_setxattr").map_err(|e| e.with_path(path))?;
+    if value.len() > XATTR_SIZE_MAX {
+        return Err(
+            Ext4Error::from_kind(Ext4ErrorKind::OutOfRange, "ext4_setxattr").with_path(path),
+        );
+    }
+    let c_path = resolve_path(path)?;
+    if flags != XattrFlags::Any {
+        let exists = match get_xattr(path, name) {
+            Ok(_) => true,
+            Err(e) if e.kind() == Ext4ErrorKind::NoData => false,
+            Err(e) => return Err(e),
+        };
+        if flags == XattrFlags::Create && exists {
+            return Err(
+                Ext4Error::from_kind(Ext4ErrorKind::Exists, "ext4_setxattr").with_path(path)
+            );
+        }
+        if flags == XattrFlags::Replace && !exists {
+            return Err(
+                Ext4Error::from_kind(Ext4ErrorKind::NoData, "ext4_setxattr").with_path(path)
+            );
+        }
+    }
+    let r = unsafe {
+        ext4_setxattr(
+            c_path.as_ptr(),
+            name.as_ptr() as _,
+            name.len(),
+            value.as_ptr() as _,
+            value.len(),
+        )
+    };
+    match r {
+        0 => Ok(()),
+        _ => {
+            error!(
+                "ext4_setxattr: rc = {}, path = {}, name = {}",
+                r, path, name
+            );
+            Err(Ext4Error::new(r, "ext4_setxattr").with_path(path))
+        }
+    }
+}
+
+/// Lists the names of the attributes of `path`, with their namespace prefix.
+pub fn list_xattrs(path: &str) -> Ext4Result<Vec<String>> {
+    let c_path = resolve_path(path)?;
+    let list = read_sized(|buf| {
+        let mut size = 0;
+        let r =
+            unsafe { ext4_listxattr(c_path.as_ptr(), buf.as_mut_ptr() as _, buf.len(), &mut size) };
+        (r, size)
+    })
+    .map_err(|r| {
+        error!("ext4_listxattr: rc = {}, path = {}", r, path);
+        Ext4Error::new(r, "ext4_listxattr").with_path(path)
+    })?;
+    // NUL-separated names.
+    Ok(list
+        .split(|&b| b == 0)
+        .filter(|name| !name.is_empty())
+        .map(|name| String::from_utf8_lossy(name).into_owned())
+        .collect())
+}
+
+/// Removes the attribute `name` of `path`.
+pub fn remove_xattr(path: &str, name: &str) -> Ext4Result<()> {
+    check_name(name, "ext4_removexattr").map_err(|e| e.with_path(path))?;
+    let c_path = resolve_path(path)?;
+    let r = unsafe { ext4_removexattr(c_path.as_ptr(), name.as_ptr() as _, name.len()) };
+    match r {
+        0 => Ok(()),
+        _ => {
+            error!(
+                "ext4_removexattr: rc = {}, path = {}, name = {}",
+                r, path, name
+            );
+            Err(Ext4Error::new(r, "ext4_removexattr").with_path(path))
+        }
+    }
+}
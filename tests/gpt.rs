@@ -0,0 +1,152 @@
+//! GPT parsing over partition tables built in memory.
+//!
+//! Run with `cargo test --features std`.
+
+use lwext4_rust::gpt::crc32;
+use lwext4_rust::{Ext4ErrorKind, Gpt, Guid, RamDisk};
+
+const LBA: usize = 512;
+const DISK_LBAS: u64 = 64;
+const ENTRIES: usize = 4;
+const ENTRY_SIZE: usize = 128;
+
+const DISK_GUID: Guid = Guid([0x11; 16]);
+const PART_GUID: Guid = Guid([0x22; 16]);
+
+fn put32(buf: &mut [u8], off: usize, v: u32) {
+    buf[off..off + 4].copy_from_slice(&v.to_le_bytes());
+}
+
+fn put64(buf: &mut [u8], off: usize, v: u64) {
+    buf[off..off + 8].copy_from_slice(&v.to_le_bytes());
+}
+
+/// Partition entry array with one Linux partition named "root" at LBAs
+/// 34..=61.
+fn entries() -> Vec<u8> {
+    let mut buf = vec![0u8; ENTRIES * ENTRY_SIZE];
+    let e = &mut buf[..ENTRY_SIZE];
+    e[0..16].copy_from_slice(&Guid::LINUX_FILESYSTEM.0);
+    e[16..32].copy_from_slice(&PART_GUID.0);
+    put64(e, 32, 34);
+    put64(e, 40, 61);
+    for (i, c) in "root".encode_utf16().enumerate() {
+        e[56 + 2 * i..58 + 2 * i].copy_from_slice(&c.to_le_bytes());
+    }
+    buf
+}
+
+/// Writes a header at `lba` pointing at the entry array at `entries_lba`.
+fn write_header(disk: &mut [u8], lba: u64, alternate: u64, entries_lba: u64) {
+    let entries = entries();
+    let off = entries_lba as usize * LBA;
+    disk[off..off + entries.len()].copy_from_slice(&entries);
+
+    let hdr = &mut disk[lba as usize * LBA..][..LBA];
+    hdr[0..8].copy_from_slice(b"EFI PART");
+    put32(hdr, 8, 0x0001_0000);
+    put32(hdr, 12, 92);
+    put64(hdr, 24, lba);
+    put64(hdr, 32, alternate);
+    put64(hdr, 40, 34);
+    put64(hdr, 48, 61);
+    hdr[56..72].copy_from_slice(&DISK_GUID.0);
+    put64(hdr, 72, entries_lba);
+    put32(hdr, 80, ENTRIES as u32);
+    put32(hdr, 84, ENTRY_SIZE as u32);
+    put32(hdr, 88, crc32(&entries));
+    let crc = crc32(&hdr[..92]);
+    put32(hdr, 16, crc);
+}
+
+/// A disk with a primary GPT and, if `backup`, a backup GPT at its end.
+fn image(backup: bool) -> Vec<u8> {
+    let mut disk = vec![0u8; DISK_LBAS as usize * LBA];
+    write_header(&mut disk, 1, DISK_LBAS - 1, 2);
+    if backup {
+        write_header(&mut disk, DISK_LBAS - 1, 1, DISK_LBAS - 2);
+    }
+    disk
+}
+
+fn read(disk: Vec<u8>) -> Result<Gpt, Ext4ErrorKind> {
+    Gpt::read::<RamDisk>(&mut RamDisk::from_vec(disk)).map_err(|e| e.kind())
+}
+
+fn check_partitions(gpt: &Gpt) {
+    assert_eq!(gpt.disk_guid, DISK_GUID);
+    assert_eq!(gpt.partitions().len(), 1);
+    let p = gpt.find_by_name("root").unwrap();
+    assert_eq!(p.index, 0);
+    assert_eq!(p.type_guid, Guid::LINUX_FILESYSTEM);
+    assert_eq!(p.unique_guid, PART_GUID);
+    assert_eq!((p.first_lba, p.last_lba), (34, 61));
+    assert_eq!(p.offset, 34 * LBA as u64);
+    assert_eq!(p.size, 28 * LBA as u64);
+    assert_eq!(gpt.find_by_type(&Guid::LINUX_FILESYSTEM).count(), 1);
+    assert_eq!(gpt.find_by_unique_guid(&PART_GUID), Some(p));
+}
+
+#[test]
+fn crc32_vector() {
+    assert_eq!(crc32(b""), 0);
+    assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+}
+
+#[test]
+fn guid_display() {
+    assert_eq!(
+        Guid::LINUX_FILESYSTEM.to_string(),
+        "0FC63DAF-8483-4772-8E79-3D69D8477DE4"
+    );
+}
+
+#[test]
+fn valid_header() {
+    check_partitions(&read(image(true)).unwrap());
+}
+
+#[test]
+fn no_gpt() {
+    let disk = vec![0u8; DISK_LBAS as usize * LBA];
+    assert_eq!(read(disk).unwrap_err(), Ext4ErrorKind::NotFound);
+}
+
+#[test]
+fn corrupted_header_crc() {
+    let mut disk = image(false);
+    // Disk GUID, covered by the header CRC.
+    disk[LBA + 56] ^= 0xff;
+    assert_eq!(read(disk).unwrap_err(), Ext4ErrorKind::Corrupted);
+}
+
+#[test]
+fn corrupted_entry_crc() {
+    let mut disk = image(false);
+    // Partition name, covered by the entry array CRC.
+    disk[2 * LBA + 56] ^= 0xff;
+    assert_eq!(read(disk).unwrap_err(), Ext4ErrorKind::Corrupted);
+}
+
+#[test]
+fn bad_entry_size() {
+    let mut disk = image(false);
+    let hdr = &mut disk[LBA..2 * LBA];
+    put32(hdr, 84, 136);
+    put32(hdr, 16, 0);
+    let crc = crc32(&hdr[..92]);
+    put32(hdr, 16, crc);
+    assert_eq!(read(disk).unwrap_err(), Ext4ErrorKind::Corrupted);
+}
+
+#[test]
+fn bad_primary_good_backup() {
+    let mut disk = image(true);
+    disk[LBA + 16] ^= 0xff;
+    check_partitions(&read(disk).unwrap());
+
+    // The backup also covers a damaged primary entry array.
+    let mut disk = image(true);
+    disk[2 * LBA + 56] ^= 0xff;
+    check_partitions(&read(disk).unwrap());
+}
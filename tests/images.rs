@@ -0,0 +1,419 @@
+//! Host tests over the ext2/ext3/ext4 images bundled in `c/ext_images.7z`.
+//!
+//! Run with `cargo test --features std`. The archive is unpacked with `7z`
+//! on first use, which must be installed.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::{Mutex, MutexGuard};
+
+use lwext4_rust::bindings::{O_CREAT, O_RDONLY, O_RDWR, O_TRUNC, SEEK_SET};
+use lwext4_rust::{
+    Ext4ErrorKind, Ext4Fs, FileDisk, InodeTypes, MountOptions, SetTime, Timespec,
+};
+
+const IMAGES: [&str; 3] = ["ext2", "ext3", "ext4"];
+
+/// lwext4 keeps global state, so the tests run one at a time.
+static LWEXT4: Mutex<()> = Mutex::new(());
+
+fn serialize() -> MutexGuard<'static, ()> {
+    LWEXT4.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Returns the directory with the unpacked images, unpacking them if needed.
+/// Panics if they can not be unpacked, so a missing `7z` fails the tests
+/// instead of passing them vacuously.
+fn images_dir() -> PathBuf {
+    let tmp = Path::new(env!("CARGO_TARGET_TMPDIR"));
+    let dir = tmp.join("ext_images");
+    if IMAGES.iter().all(|img| dir.join(img).exists()) {
+        return dir;
+    }
+    let archive = Path::new(env!("CARGO_MANIFEST_DIR")).join("c/ext_images.7z");
+    let out = Command::new("7z")
+        .arg("x")
+        .arg("-y")
+        .arg(format!("-o{}", tmp.display()))
+        .arg(&archive)
+        .output()
+        .unwrap_or_else(|e| panic!("can not run 7z to unpack {}: {}", archive.display(), e));
+    assert!(
+        out.status.success(),
+        "7z failed to unpack {}: {}",
+        archive.display(),
+        String::from_utf8_lossy(&out.stderr)
+    );
+    for img in IMAGES {
+        assert!(dir.join(img).exists(), "{} is missing from {}", img, archive.display());
+    }
+    dir
+}
+
+/// Runs `f` on a scratch copy of every bundled image.
+fn for_each_image(test: &str, f: impl Fn(&str, &Path)) {
+    let _guard = serialize();
+    let dir = images_dir();
+    for img in IMAGES {
+        let copy = Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!("{}-{}.img", test, img));
+        fs::copy(dir.join(img), &copy).unwrap();
+        f(img, &copy);
+        fs::remove_file(&copy).unwrap();
+    }
+}
+
+const NOW: Timespec = Timespec {
+    sec: 1_700_000_000,
+    nsec: 500,
+};
+
+fn mount(path: &Path) -> Ext4Fs<FileDisk> {
+    let disk = FileDisk::open(path).unwrap();
+    let options = MountOptions::new().mount_point("/img/").clock(|| NOW);
+    Ext4Fs::new(disk, options).unwrap()
+}
+
+fn write_file(fs: &Ext4Fs<FileDisk>, path: &str, data: &[u8]) {
+    let mut file = fs.open(path, (O_RDWR | O_CREAT | O_TRUNC) as i32).unwrap();
+    assert_eq!(file.write(data).unwrap(), data.len());
+}
+
+fn read_file(fs: &Ext4Fs<FileDisk>, path: &str) -> Vec<u8> {
+    let mut file = fs.open(path, O_RDONLY as i32).unwrap();
+    let mut buf = vec![0u8; file.size() as usize];
+    assert_eq!(file.read(&mut buf).unwrap(), buf.len());
+    buf
+}
+
+fn list(fs: &Ext4Fs<FileDisk>, path: &str) -> Vec<String> {
+    fs.open_dir(path)
+        .unwrap()
+        .map(|entry| entry.unwrap().name_str().unwrap().to_string())
+        .collect()
+}
+
+#[test]
+fn list_root() {
+    for_each_image("list_root", |img, path| {
+        let fs = mount(path);
+        let names = list(&fs, "/");
+        for name in [".", "..", "lost+found"] {
+            assert!(names.iter().any(|n| n == name), "{}: {:?}", img, names);
+        }
+        assert!(fs.metadata("/").unwrap().is_dir());
+        fs.unmount().unwrap();
+    });
+}
+
+#[test]
+fn read_dir() {
+    for_each_image("read_dir", |img, path| {
+        let fs = mount(path);
+        fs.create_dir("d").unwrap();
+        write_file(&fs, "d/file", b"");
+        fs.create_dir("d/sub").unwrap();
+
+        let mut dir = fs.open_dir("d").unwrap();
+        dir.set_skip_dots(true);
+        let mut entries: Vec<_> = dir.by_ref().map(|e| e.unwrap()).collect();
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        assert_eq!(entries.len(), 2, "{}", img);
+        assert_eq!(entries[0].name, b"file");
+        assert_eq!(entries[0].inode, fs.metadata("d/file").unwrap().ino);
+        assert_eq!(entries[1].name, b"sub");
+        assert!(dir.next().is_none());
+
+        dir.rewind();
+        dir.set_skip_dots(false);
+        assert_eq!(dir.count(), 4);
+        fs.unmount().unwrap();
+    });
+}
+
+#[test]
+fn write_and_read() {
+    for_each_image("write_and_read", |img, path| {
+        let fs = mount(path);
+        let data: Vec<u8> = (0..100_000u32).map(|i| i as u8).collect();
+        write_file(&fs, "data.bin", &data);
+        assert_eq!(read_file(&fs, "data.bin"), data, "{}", img);
+
+        let mut file = fs.open("data.bin", O_RDWR as i32).unwrap();
+        file.seek(4096, SEEK_SET).unwrap();
+        assert_eq!(file.tell(), 4096);
+        let mut buf = [0u8; 16];
+        file.read(&mut buf).unwrap();
+        assert_eq!(&buf[..], &data[4096..4112]);
+        drop(file);
+
+        let meta = fs.metadata("data.bin").unwrap();
+        assert!(meta.is_file());
+        assert_eq!(meta.size, data.len() as u64);
+        fs.unmount().unwrap();
+    });
+}
+
+#[test]
+fn create_dir_and_rename() {
+    for_each_image("create_dir_and_rename", |img, path| {
+        let fs = mount(path);
+        fs.create_dir("dir").unwrap();
+        write_file(&fs, "dir/a.txt", b"a");
+        fs.rename("dir/a.txt", "dir/b.txt").unwrap();
+        assert!(!fs.exists("dir/a.txt", InodeTypes::EXT4_DE_REG_FILE));
+        assert_eq!(read_file(&fs, "dir/b.txt"), b"a");
+
+        fs.rename("dir", "moved").unwrap();
+        assert!(fs.exists("moved", InodeTypes::EXT4_DE_DIR), "{}", img);
+        assert!(list(&fs, "moved").iter().any(|n| n == "b.txt"));
+
+        fs.remove("moved").unwrap();
+        let err = fs.metadata("moved").unwrap_err();
+        assert_eq!(err.kind(), Ext4ErrorKind::NotFound);
+        fs.unmount().unwrap();
+    });
+}
+
+#[test]
+fn stat_and_fstat() {
+    for_each_image("stat_and_fstat", |img, path| {
+        let fs = mount(path);
+        write_file(&fs, "f", &[1u8; 5000]);
+        let meta = fs.metadata("f").unwrap();
+        assert!(meta.is_file(), "{}", img);
+        assert_eq!(meta.nlink, 1);
+        assert!(meta.blocks * 512 >= 5000);
+        assert!(meta.blksize >= 1024);
+        assert!(meta.mtime.nsec < 1_000_000_000);
+
+        let file = fs.open("f", O_RDONLY as i32).unwrap();
+        let fmeta = file.metadata().unwrap();
+        assert_eq!(fmeta.ino, meta.ino);
+        assert_eq!(fmeta.size, meta.size);
+        assert_eq!(fmeta.mtime, meta.mtime);
+        drop(file);
+
+        fs.link("f", "g").unwrap();
+        assert_eq!(fs.metadata("g").unwrap().nlink, 2, "{}", img);
+        assert!(fs.metadata("/").unwrap().nlink >= 3);
+        fs.unmount().unwrap();
+    });
+}
+
+#[test]
+fn chmod_chown_utimens() {
+    for_each_image("chmod_chown_utimens", |img, path| {
+        let fs = mount(path);
+        write_file(&fs, "f", b"x");
+        fs.set_permissions("f", 0o640).unwrap();
+        fs.set_owner("f", Some(70_000), None).unwrap();
+        let meta = fs.metadata("f").unwrap();
+        assert_eq!(meta.mode, 0o640, "{}", img);
+        assert!(meta.is_file());
+        assert_eq!(meta.uid, 70_000);
+        assert_eq!(meta.ctime.sec, NOW.sec);
+
+        let err = fs.set_permissions("f", 0o10000).unwrap_err();
+        assert_eq!(err.kind(), Ext4ErrorKind::InvalidInput);
+
+        let then = Timespec { sec: 1_000_000, nsec: 0 };
+        fs.set_times("f", SetTime::At(then), SetTime::Omit).unwrap();
+        let after = fs.metadata("f").unwrap();
+        assert_eq!(after.atime, then);
+        assert_eq!(after.mtime, meta.mtime);
+
+        let mut file = fs.open("f", O_RDWR as i32).unwrap();
+        file.set_times(SetTime::Omit, SetTime::Now).unwrap();
+        file.set_owner(None, Some(5)).unwrap();
+        let fmeta = file.metadata().unwrap();
+        assert_eq!(fmeta.mtime.sec, NOW.sec);
+        assert_eq!(fmeta.gid, 5);
+        drop(file);
+        fs.unmount().unwrap();
+    });
+}
+
+#[cfg(feature = "xattr")]
+#[test]
+fn xattrs() {
+    use lwext4_rust::XattrFlags;
+
+    for_each_image("xattrs", |img, path| {
+        let fs = mount(path);
+        write_file(&fs, "f", b"x");
+        fs.set_xattr("f", "user.comment", b"hello", XattrFlags::Create)
+            .unwrap();
+        fs.set_xattr("f", "security.selinux", b"system_u:object_r:etc_t", XattrFlags::Any)
+            .unwrap();
+        assert_eq!(fs.get_xattr("f", "user.comment").unwrap(), b"hello", "{}", img);
+
+        let err = fs
+            .set_xattr("f", "user.comment", b"again", XattrFlags::Create)
+            .unwrap_err();
+        assert_eq!(err.kind(), Ext4ErrorKind::Exists);
+        let err = fs
+            .set_xattr("f", "user.missing", b"v", XattrFlags::Replace)
+            .unwrap_err();
+        assert_eq!(err.kind(), Ext4ErrorKind::NoData);
+        let err = fs.set_xattr("f", "comment", b"v", XattrFlags::Any).unwrap_err();
+        assert_eq!(err.kind(), Ext4ErrorKind::InvalidInput);
+
+        let long = vec![7u8; 1000];
+        fs.set_xattr("f", "user.comment", &long, XattrFlags::Replace)
+            .unwrap();
+        assert_eq!(fs.get_xattr("f", "user.comment").unwrap(), long);
+
+        let mut names = fs.list_xattrs("f").unwrap();
+        names.sort();
+        assert_eq!(names, ["security.selinux", "user.comment"]);
+
+        fs.remove_xattr("f", "user.comment").unwrap();
+        let err = fs.get_xattr("f", "user.comment").unwrap_err();
+        assert_eq!(err.kind(), Ext4ErrorKind::NoData);
+        fs.unmount().unwrap();
+    });
+}
+
+#[test]
+fn mknod() {
+    for_each_image("mknod", |img, path| {
+        let fs = mount(path);
+        fs.create_dir("dev").unwrap();
+        fs.mknod("dev/null", InodeTypes::EXT4_DE_CHRDEV, 0o666, 1, 3)
+            .unwrap();
+        fs.mknod("dev/sda", InodeTypes::EXT4_DE_BLKDEV, 0o660, 259, 70_000)
+            .unwrap();
+        fs.mknod("pipe", InodeTypes::EXT4_DE_FIFO, 0o600, 0, 0).unwrap();
+
+        let null = fs.metadata("dev/null").unwrap();
+        assert_eq!(null.file_type, InodeTypes::EXT4_INODE_MODE_CHARDEV, "{}", img);
+        assert_eq!((null.rdev_major, null.rdev_minor), (1, 3));
+        assert_eq!(null.mode, 0o666);
+        let sda = fs.metadata("dev/sda").unwrap();
+        assert_eq!(sda.file_type, InodeTypes::EXT4_INODE_MODE_BLOCKDEV);
+        assert_eq!((sda.rdev_major, sda.rdev_minor), (259, 70_000));
+        let pipe = fs.metadata("pipe").unwrap();
+        assert_eq!(pipe.file_type, InodeTypes::EXT4_INODE_MODE_FIFO);
+        assert_eq!(pipe.mode, 0o600);
+
+        let err = fs
+            .mknod("file", InodeTypes::EXT4_DE_REG_FILE, 0o644, 0, 0)
+            .unwrap_err();
+        assert_eq!(err.kind(), Ext4ErrorKind::InvalidInput);
+        fs.unmount().unwrap();
+    });
+}
+
+#[test]
+fn mknod_failed_chmod() {
+    for_each_image("mknod_failed_chmod", |img, path| {
+        // A clock lwext4 can not store makes setting the permissions fail
+        // after the node was created.
+        let disk = FileDisk::open(path).unwrap();
+        let options = MountOptions::new().mount_point("/img/").clock(|| Timespec {
+            sec: 0,
+            nsec: 1_000_000_000,
+        });
+        let fs = Ext4Fs::new(disk, options).unwrap();
+        let err = fs
+            .mknod("pipe", InodeTypes::EXT4_DE_FIFO, 0o600, 0, 0)
+            .unwrap_err();
+        assert_eq!(err.kind(), Ext4ErrorKind::InvalidInput, "{}", img);
+        let err = fs.metadata("pipe").unwrap_err();
+        assert_eq!(err.kind(), Ext4ErrorKind::NotFound, "{}", img);
+        assert!(!list(&fs, "/").iter().any(|n| n == "pipe"));
+        fs.unmount().unwrap();
+    });
+}
+
+#[test]
+fn dir_entry_types() {
+    for_each_image("dir_entry_types", |img, path| {
+        let fs = mount(path);
+        fs.create_dir("d").unwrap();
+        fs.create_dir("d/dir").unwrap();
+        write_file(&fs, "d/file", b"");
+        fs.symlink("file", "d/sym").unwrap();
+        fs.mknod("d/chr", InodeTypes::EXT4_DE_CHRDEV, 0o600, 1, 3)
+            .unwrap();
+        fs.mknod("d/blk", InodeTypes::EXT4_DE_BLKDEV, 0o600, 8, 0)
+            .unwrap();
+        fs.mknod("d/fifo", InodeTypes::EXT4_DE_FIFO, 0o600, 0, 0)
+            .unwrap();
+        fs.mknod("d/sock", InodeTypes::EXT4_DE_SOCK, 0o600, 0, 0)
+            .unwrap();
+
+        let mut dir = fs.open_dir("d").unwrap();
+        dir.set_skip_dots(true);
+        let mut count = 0;
+        for entry in dir {
+            let entry = entry.unwrap();
+            let name = entry.name_str().unwrap();
+            let meta = fs.metadata(&format!("d/{}", name)).unwrap();
+            assert_eq!(entry.file_type, meta.file_type, "{}: {}", img, name);
+            count += 1;
+        }
+        assert_eq!(count, 7, "{}", img);
+        fs.unmount().unwrap();
+    });
+}
+
+#[test]
+fn hard_link() {
+    for_each_image("hard_link", |img, path| {
+        let fs = mount(path);
+        write_file(&fs, "orig", b"linked");
+        fs.link("orig", "link").unwrap();
+        assert_eq!(
+            fs.metadata("orig").unwrap().ino,
+            fs.metadata("link").unwrap().ino
+        );
+        fs.remove("orig").unwrap();
+        assert_eq!(read_file(&fs, "link"), b"linked", "{}", img);
+        fs.unmount().unwrap();
+    });
+}
+
+#[test]
+fn symlink() {
+    for_each_image("symlink", |img, path| {
+        let fs = mount(path);
+        write_file(&fs, "target", b"t");
+        fs.symlink("target", "sym").unwrap();
+        assert!(fs.metadata("sym").unwrap().is_symlink(), "{}", img);
+        let mut buf = [0u8; 64];
+        let n = fs.read_link("sym", &mut buf).unwrap();
+        assert_eq!(&buf[..n], b"target");
+        fs.unmount().unwrap();
+    });
+}
+
+#[test]
+fn truncate() {
+    for_each_image("truncate", |img, path| {
+        let fs = mount(path);
+        write_file(&fs, "t", &[7u8; 10_000]);
+        let mut file = fs.open("t", O_RDWR as i32).unwrap();
+        file.truncate(100).unwrap();
+        assert_eq!(file.size(), 100);
+        drop(file);
+        assert_eq!(read_file(&fs, "t"), [7u8; 100], "{}", img);
+        fs.unmount().unwrap();
+    });
+}
+
+#[test]
+fn remount_persistence() {
+    for_each_image("remount_persistence", |img, path| {
+        let fs = mount(path);
+        fs.create_dir("keep").unwrap();
+        write_file(&fs, "keep/file", b"still here");
+        fs.sync().unwrap();
+        fs.unmount().unwrap();
+
+        let fs = mount(path);
+        assert_eq!(read_file(&fs, "keep/file"), b"still here", "{}", img);
+        fs.unmount().unwrap();
+    });
+}
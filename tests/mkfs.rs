@@ -0,0 +1,75 @@
+//! `mkfs` option checks and formatting of in-memory disks.
+//!
+//! Run with `cargo test --features std`.
+
+use std::sync::{Mutex, MutexGuard};
+
+use lwext4_rust::mkfs::{format, read_info};
+use lwext4_rust::{Ext4ErrorKind, FsType, MkfsOptions, RamDisk};
+
+const DISK_SIZE: usize = 8 << 20;
+
+/// lwext4 keeps global state, so the tests run one at a time.
+static LWEXT4: Mutex<()> = Mutex::new(());
+
+fn serialize() -> MutexGuard<'static, ()> {
+    LWEXT4.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn format_err(options: MkfsOptions) -> Ext4ErrorKind {
+    let _guard = serialize();
+    let mut disk = RamDisk::new(DISK_SIZE);
+    let err = format::<RamDisk>(&mut disk, &options).unwrap_err();
+    // Rejected before anything is written.
+    assert!(disk.as_bytes().iter().all(|&b| b == 0));
+    err.kind()
+}
+
+#[test]
+fn label_length() {
+    let long = "seventeen-bytes!!";
+    assert_eq!(long.len(), 17);
+    let options = MkfsOptions::new(FsType::Ext4).label(long);
+    assert_eq!(format_err(options), Ext4ErrorKind::InvalidInput);
+
+    let _guard = serialize();
+    let mut disk = RamDisk::new(DISK_SIZE);
+    let options = MkfsOptions::new(FsType::Ext4).label(&long[..16]);
+    format::<RamDisk>(&mut disk, &options).unwrap();
+}
+
+#[test]
+fn block_size() {
+    for size in [512, 1000, 3072, 131072] {
+        let options = MkfsOptions::new(FsType::Ext4).block_size(size);
+        assert_eq!(format_err(options), Ext4ErrorKind::InvalidInput, "{}", size);
+    }
+
+    let _guard = serialize();
+    for size in [1024, 2048, 4096] {
+        let mut disk = RamDisk::new(DISK_SIZE);
+        let options = MkfsOptions::new(FsType::Ext4).block_size(size);
+        let info = format::<RamDisk>(&mut disk, &options).unwrap();
+        assert_eq!(info.block_size, size);
+        assert_eq!(
+            read_info::<RamDisk>(&mut disk, 0, 0).unwrap().block_size,
+            size
+        );
+    }
+}
+
+#[test]
+fn inode_size() {
+    for size in [64, 200, 8192] {
+        let options = MkfsOptions::new(FsType::Ext4)
+            .block_size(4096)
+            .inode_size(size);
+        assert_eq!(format_err(options), Ext4ErrorKind::InvalidInput, "{}", size);
+    }
+}
+
+#[test]
+fn ext2_journal() {
+    let options = MkfsOptions::new(FsType::Ext2).journal(true);
+    assert_eq!(format_err(options), Ext4ErrorKind::InvalidInput);
+}
@@ -0,0 +1,131 @@
+//! `RamDisk` as a block device and as the backing of a freshly formatted
+//! volume.
+//!
+//! Run with `cargo test --features std`.
+
+use std::sync::{Mutex, MutexGuard};
+
+use lwext4_rust::bindings::{EIO, O_CREAT, O_RDONLY, O_RDWR, O_TRUNC};
+use lwext4_rust::mkfs::format;
+use lwext4_rust::{BlockDevice, Ext4ErrorKind, Ext4Fs, FsType, MkfsOptions, MountOptions, RamDisk};
+
+/// lwext4 keeps global state, so the tests using it run one at a time.
+static LWEXT4: Mutex<()> = Mutex::new(());
+
+fn serialize() -> MutexGuard<'static, ()> {
+    LWEXT4.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+static IMAGE: [u8; 2048] = {
+    let mut image = [0u8; 2048];
+    let mut i = 0;
+    while i < image.len() {
+        image[i] = i as u8;
+        i += 1;
+    }
+    image
+};
+
+#[test]
+fn block_size() {
+    for size in [0, 3, 1000, 4097] {
+        let err = RamDisk::new(8192).with_block_size(size).unwrap_err();
+        assert_eq!(err.kind(), Ext4ErrorKind::InvalidInput, "{}", size);
+    }
+    let mut disk = RamDisk::new(8192).with_block_size(4096).unwrap();
+    assert_eq!(disk.block_size(), 4096);
+    assert_eq!(disk.num_blocks(), 2);
+}
+
+#[test]
+fn copy_on_write() {
+    let mut disk = RamDisk::from_static(&IMAGE);
+    assert!(disk.is_borrowed());
+
+    let mut buf = [0u8; 512];
+    disk.read_blocks(1, 1, &mut buf).unwrap();
+    assert_eq!(&buf[..], &IMAGE[512..1024]);
+    assert!(disk.is_borrowed());
+    assert_eq!(disk.clone().into_vec(), &IMAGE[..]);
+
+    let untouched = disk.clone();
+    disk.write_blocks(2, 1, &[0xaa; 512]).unwrap();
+    assert!(!disk.is_borrowed());
+    assert!(untouched.is_borrowed());
+    assert!(IMAGE[1024..1536]
+        .iter()
+        .enumerate()
+        .all(|(i, &b)| b == i as u8));
+    assert_eq!(&disk.as_bytes()[..1024], &IMAGE[..1024]);
+    assert_eq!(&disk.as_bytes()[1024..1536], &[0xaa; 512][..]);
+    assert_eq!(&disk.as_bytes()[1536..], &IMAGE[1536..]);
+}
+
+#[test]
+fn out_of_range() {
+    let mut disk = RamDisk::from_static(&IMAGE);
+    let mut buf = [0u8; 1024];
+    // Past the end, straddling the end, overflowing and with a buffer that
+    // does not match the sector count.
+    assert_eq!(disk.read_blocks(4, 1, &mut buf[..512]), Err(EIO as i32));
+    assert_eq!(disk.read_blocks(3, 2, &mut buf), Err(EIO as i32));
+    assert_eq!(
+        disk.read_blocks(u64::MAX, 1, &mut buf[..512]),
+        Err(EIO as i32)
+    );
+    assert_eq!(disk.read_blocks(0, 1, &mut buf), Err(EIO as i32));
+
+    assert_eq!(disk.write_blocks(4, 1, &buf[..512]), Err(EIO as i32));
+    assert_eq!(disk.write_blocks(3, 2, &buf), Err(EIO as i32));
+    assert_eq!(disk.write_blocks(u64::MAX, 1, &buf[..512]), Err(EIO as i32));
+    assert_eq!(disk.write_blocks(0, 1, &buf), Err(EIO as i32));
+    // Rejected writes leave the image alone.
+    assert!(disk.is_borrowed());
+
+    disk.read_blocks(3, 1, &mut buf[..512]).unwrap();
+    assert_eq!(&buf[..512], &IMAGE[1536..]);
+}
+
+#[test]
+fn format_and_remount() {
+    let _guard = serialize();
+    let mut disk = RamDisk::new(16 << 20);
+    format::<RamDisk>(&mut disk, &MkfsOptions::new(FsType::Ext4)).unwrap();
+
+    let options = || MountOptions::new().mount_point("/ram/");
+    let data: Vec<u8> = (0..50_000u32).map(|i| (i * 7) as u8).collect();
+    let fs = Ext4Fs::<RamDisk>::new(disk, options()).unwrap();
+    fs.create_dir("dir").unwrap();
+    let mut file = fs
+        .open("dir/data", (O_RDWR | O_CREAT | O_TRUNC) as i32)
+        .unwrap();
+    assert_eq!(file.write(&data).unwrap(), data.len());
+    drop(file);
+    let disk = fs.into_device().unwrap();
+
+    let fs = Ext4Fs::<RamDisk>::new(disk, options()).unwrap();
+    let mut file = fs.open("dir/data", O_RDONLY as i32).unwrap();
+    let mut buf = vec![0u8; file.size() as usize];
+    assert_eq!(file.read(&mut buf).unwrap(), data.len());
+    assert_eq!(buf, data);
+    drop(file);
+    fs.unmount().unwrap();
+}
+
+#[test]
+fn drop_mounted() {
+    let _guard = serialize();
+    let mut disk = RamDisk::new(16 << 20);
+    format::<RamDisk>(&mut disk, &MkfsOptions::new(FsType::Ext4)).unwrap();
+
+    // Dropping a mounted volume unmounts it and releases the block device,
+    // whose cache lwext4 swapped for the one of the mount point.
+    let options = || MountOptions::new().mount_point("/ram/");
+    let fs = Ext4Fs::<RamDisk>::new(disk.clone(), options()).unwrap();
+    fs.create_dir("dir").unwrap();
+    drop(fs);
+
+    let fs = Ext4Fs::<RamDisk>::new(disk, options()).unwrap();
+    fs.create_dir("dir").unwrap();
+    fs.unmount().unwrap();
+}